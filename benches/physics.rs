@@ -0,0 +1,169 @@
+//! Benchmarks of the hot paths of the simulation, on synthetic boards so that they run anywhere
+//! (no input files, no CUDA). Run them with `cargo bench --no-default-features`.
+
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use physics_apple::{
+    config::{ForceLaw, PhysicsConfig, PhysicsMode, RenderConfig, RenderStyle, SpawnConfig},
+    physics::engine::gravitational_force,
+    render, Attractor, Board, Config,
+};
+
+const FORCE_LAWS: [ForceLaw; 3] = [
+    ForceLaw::InverseCube,
+    ForceLaw::InverseSquare,
+    ForceLaw::Inverse,
+];
+
+/// Masks with few, many and outline-like attractors, as the extractors produce them.
+#[derive(Clone, Copy, Debug)]
+enum Mask {
+    /// One attractor every 16 pixels in each direction.
+    Sparse,
+    /// A filled disc covering about a third of the board.
+    Dense,
+    /// The outline of a square, like the `canny` extractor would give.
+    Edges,
+}
+
+impl Mask {
+    fn attractors(self, size: u32, physics: &PhysicsConfig) -> Vec<Attractor> {
+        let center = size as f32 / 2.0;
+        let radius = size as f32 / 3.0;
+        let (low, high) = (size / 4, size * 3 / 4);
+        (0..size)
+            .flat_map(|y| (0..size).map(move |x| (x, y)))
+            .filter(|&(x, y)| match self {
+                Mask::Sparse => x % 16 == 8 && y % 16 == 8,
+                Mask::Dense => {
+                    let (dx, dy) = (x as f32 - center, y as f32 - center);
+                    dx * dx + dy * dy <= radius * radius
+                }
+                Mask::Edges => {
+                    (low..=high).contains(&x)
+                        && (low..=high).contains(&y)
+                        && (x == low || x == high || y == low || y == high)
+                }
+            })
+            .map(|(x, y)| Attractor {
+                x,
+                y,
+                mass: physics.attractor_mass,
+            })
+            .collect()
+    }
+}
+
+/// A seeded board of `size`x`size` with the field of `mask` and `particles` particles on it.
+fn board(size: u32, mask: Mask, particles: u32, physics: PhysicsConfig) -> Board {
+    let mut board = Board::new(size, size, physics);
+    board.set_attractors(mask.attractors(size, &physics));
+    board.generate_static_field();
+    board.seed(0);
+    board.spawn_particles(particles, &SpawnConfig::default());
+    board
+}
+
+fn force(c: &mut Criterion) {
+    let mut group = c.benchmark_group("gravitational_force");
+    for law in FORCE_LAWS {
+        let physics = PhysicsConfig {
+            force_law: law,
+            ..PhysicsConfig::default()
+        };
+        group.bench_function(format!("{:?}", law), |b| {
+            b.iter(|| {
+                gravitational_force(
+                    black_box(3),
+                    black_box(5),
+                    physics.particle_mass,
+                    black_box(40),
+                    black_box(21),
+                    physics.attractor_mass,
+                    &physics,
+                )
+            })
+        });
+    }
+    group.finish();
+}
+
+fn static_field(c: &mut Criterion) {
+    let mut group = c.benchmark_group("generate_static_field");
+    // Every field takes milliseconds, fewer samples keep the suite short.
+    group.sample_size(10);
+    for mask in [Mask::Sparse, Mask::Dense, Mask::Edges] {
+        for law in FORCE_LAWS {
+            let physics = PhysicsConfig {
+                force_law: law,
+                ..PhysicsConfig::default()
+            };
+            let mut board = board(64, mask, 0, physics);
+            group.bench_function(
+                BenchmarkId::new(format!("{:?}", mask), format!("{:?}", law)),
+                |b| b.iter(|| board.generate_static_field()),
+            );
+        }
+    }
+    group.finish();
+}
+
+fn update(c: &mut Criterion) {
+    let mut group = c.benchmark_group("update");
+    for mode in [PhysicsMode::Gravity, PhysicsMode::Assignment] {
+        for particles in [1_000, 10_000, 50_000] {
+            let physics = PhysicsConfig {
+                mode,
+                ..PhysicsConfig::default()
+            };
+            let mut board = board(128, Mask::Edges, particles, physics);
+            // The first update of the assignment mode matches particles to targets, which is
+            // not what is measured here.
+            board.update();
+            group.bench_with_input(
+                BenchmarkId::new(format!("{:?}", mode), particles),
+                &particles,
+                |b, _| b.iter(|| board.update()),
+            );
+        }
+    }
+    group.finish();
+}
+
+fn draw(c: &mut Criterion) {
+    let mut group = c.benchmark_group("draw_particles");
+    for particles in [1_000, 10_000, 50_000] {
+        let board = board(128, Mask::Edges, particles, PhysicsConfig::default());
+        for style in [
+            RenderStyle::Solid,
+            RenderStyle::Density,
+            RenderStyle::Trails,
+        ] {
+            let render = RenderConfig {
+                style,
+                ..RenderConfig::default()
+            };
+            let mut buffer = vec![0u8; (board.width * board.heigth * 4) as usize];
+            group.bench_with_input(
+                BenchmarkId::new(format!("{:?}", style), particles),
+                &particles,
+                |b, _| b.iter(|| board.draw_particles(&mut buffer, &render)),
+            );
+        }
+
+        // Saved frames are drawn at a larger scale, with a splat per particle.
+        let mut config = Config::default();
+        config.render.render_scale = 4;
+        let mut buffer = render::render_buffer(&board, &config);
+        group.bench_with_input(
+            BenchmarkId::new("Upscaled", particles),
+            &particles,
+            |b, _| b.iter(|| render::draw(&board, &mut buffer, &config)),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, force, static_field, update, draw);
+criterion_main!(benches);
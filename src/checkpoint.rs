@@ -0,0 +1,363 @@
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use crate::{
+    config::Config,
+    error::{Error, Result},
+    physics::board::Board,
+    schedule::Playback,
+};
+
+/// Start of every checkpoint file, followed by the format version.
+const CHECKPOINT_MAGIC: &[u8; 8] = b"PACHECK\0";
+
+/// Version of the checkpoint format. Bump it whenever the layout changes, older files are then
+/// refused instead of being misread.
+const CHECKPOINT_VERSION: u32 = 2;
+
+/// Longest configuration a checkpoint may hold. Real ones are a few kilobytes, a longer one means
+/// the file is corrupt.
+const MAX_CONFIG_LENGTH: u64 = 1 << 20;
+
+/// Everything needed to continue `simulate-sequence --save-to-file` where it stopped, so that the
+/// remaining frames are identical to those of an uninterrupted run.
+pub struct Checkpoint {
+    /// The configuration the run was started with.
+    pub config: Config,
+    /// Number of input frames in the sequence, to notice resuming with other frames.
+    pub input_frames: u64,
+    /// The next output frame to render.
+    pub output_frame: u64,
+    /// Updates run so far, and the input frame the field comes from. See `Playback::position`.
+    pub position: (u64, usize),
+    pub board: Board,
+    /// The render buffer holding the last saved frame, which the `trails` style draws over.
+    pub buffer: Vec<u8>,
+}
+
+/// Writes a checkpoint to `path`. The file is written next to it first and then renamed, so that
+/// an interruption while writing leaves the previous checkpoint intact.
+///
+/// # Arguments
+/// - path: Where to write the checkpoint.
+/// - config: The configuration of the run.
+/// - input_frames: Number of input frames in the sequence.
+/// - output_frame: The next output frame to render.
+/// - playback: The playback of the sequence.
+/// - board, buffer: The board and the render buffer.
+pub fn save(
+    path: &Path,
+    config: &Config,
+    input_frames: u64,
+    output_frame: u64,
+    playback: &Playback,
+    board: &Board,
+    buffer: &[u8],
+) -> Result<()> {
+    let config = toml::to_string(config).map_err(|err| Error::Encode {
+        path: path.to_path_buf(),
+        message: err.to_string(),
+    })?;
+    let partial_path = path.with_extension("partial");
+
+    let write = || -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(&partial_path)?);
+
+        writer.write_all(CHECKPOINT_MAGIC)?;
+        writer.write_u32::<LittleEndian>(CHECKPOINT_VERSION)?;
+
+        writer.write_u64::<LittleEndian>(config.len() as u64)?;
+        writer.write_all(config.as_bytes())?;
+
+        writer.write_u64::<LittleEndian>(input_frames)?;
+        writer.write_u64::<LittleEndian>(output_frame)?;
+        let (step, input) = playback.position();
+        writer.write_u64::<LittleEndian>(step)?;
+        writer.write_u64::<LittleEndian>(input as u64)?;
+
+        board.write_state(&mut writer)?;
+        writer.write_u64::<LittleEndian>(buffer.len() as u64)?;
+        writer.write_all(buffer)?;
+
+        writer.into_inner()?.sync_all()
+    };
+    write().map_err(|err| Error::io(&partial_path, err))?;
+    std::fs::rename(&partial_path, path).map_err(|err| Error::io(path, err))
+}
+
+/// Reads a checkpoint written by `save`. A corrupt file fails with `Error::Decode`: every length
+/// is checked against what the rest of the file implies before it is allocated.
+pub fn load(path: &Path) -> Result<Checkpoint> {
+    let file = File::open(path).map_err(|err| Error::io(path, err))?;
+    let mut reader = BufReader::new(file);
+    let read_error = |err| Error::read(path, err);
+
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic).map_err(read_error)?;
+    if &magic != CHECKPOINT_MAGIC {
+        return Err(Error::decode(path, "this is not a checkpoint"));
+    }
+    let version = reader.read_u32::<LittleEndian>().map_err(read_error)?;
+    if version != CHECKPOINT_VERSION {
+        return Err(Error::decode(
+            path,
+            format!(
+                "this is a version {} checkpoint, only version {} is supported",
+                version, CHECKPOINT_VERSION
+            ),
+        ));
+    }
+
+    let config_length = reader.read_u64::<LittleEndian>().map_err(read_error)?;
+    if config_length > MAX_CONFIG_LENGTH {
+        return Err(Error::decode(
+            path,
+            format!(
+                "the configuration is {} bytes long, at most {} are expected",
+                config_length, MAX_CONFIG_LENGTH
+            ),
+        ));
+    }
+    let mut config = vec![0u8; config_length as usize];
+    reader.read_exact(&mut config).map_err(read_error)?;
+    let config = std::str::from_utf8(&config).map_err(|err| Error::decode(path, err))?;
+    let config: Config = toml::from_str(config).map_err(|err| Error::decode(path, err))?;
+    config.validate().map_err(|err| Error::decode(path, err))?;
+
+    let mut read_u64 = || reader.read_u64::<LittleEndian>().map_err(read_error);
+    let input_frames = read_u64()?;
+    let output_frame = read_u64()?;
+    let step = read_u64()?;
+    let input = read_u64()? as usize;
+
+    let board = Board::read_state(&mut reader, config.physics).map_err(read_error)?;
+    let buffer_length = reader.read_u64::<LittleEndian>().map_err(read_error)?;
+    // The size `render_buffer` allocates for the restored board and configuration.
+    let scale = config.render.render_scale;
+    let expected = board
+        .width
+        .checked_mul(scale)
+        .zip(board.heigth.checked_mul(scale))
+        .and_then(|(width, height)| (width as u64 * height as u64).checked_mul(4));
+    if Some(buffer_length) != expected {
+        return Err(Error::decode(
+            path,
+            format!(
+                "the render buffer is {} bytes long, which doesn't fit a {}x{} board rendered at \
+                 scale {}",
+                buffer_length, board.width, board.heigth, scale
+            ),
+        ));
+    }
+    let mut buffer = vec![0u8; buffer_length as usize];
+    reader.read_exact(&mut buffer).map_err(read_error)?;
+
+    Ok(Checkpoint {
+        config,
+        input_frames,
+        output_frame,
+        position: (step, input),
+        board,
+        buffer,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GrayImage, Luma};
+
+    use super::*;
+    use crate::{
+        config::{FrameRate, Interpolation, RenderStyle, SequenceConfig, SpawnConfig},
+        frames::Frame,
+        physics::seeded_board,
+        render,
+    };
+
+    /// Offset of the configuration length, after the magic and the version.
+    const CONFIG_LENGTH_OFFSET: usize = 12;
+
+    /// Saves the checkpoint of a small seeded board, and returns its bytes with the offset of the
+    /// board state in them.
+    fn checkpoint(path: &Path) -> (Vec<u8>, usize) {
+        let config = Config::default();
+        let mut board = Board::new(16, 8, config.physics);
+        board.seed(3);
+        board.spawn_particles(20, &SpawnConfig::default());
+        let buffer = crate::render::render_buffer(&board, &config);
+        let playback = Playback::new(vec![], &config);
+        save(path, &config, 1, 0, &playback, &board, &buffer).unwrap();
+
+        let bytes = std::fs::read(path).unwrap();
+        let config_length = toml::to_string(&config).unwrap().len();
+        (bytes, CONFIG_LENGTH_OFFSET + 8 + config_length + 4 * 8)
+    }
+
+    fn load_corrupt(
+        path: &Path,
+        bytes: &mut [u8],
+        offset: usize,
+        value: &[u8],
+    ) -> Result<Checkpoint> {
+        bytes[offset..offset + value.len()].copy_from_slice(value);
+        std::fs::write(path, &bytes).unwrap();
+        load(path)
+    }
+
+    #[test]
+    fn saved_checkpoints_load() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("run.checkpoint");
+        checkpoint(&path);
+        let loaded = load(&path).unwrap();
+        assert_eq!((loaded.board.width, loaded.board.heigth), (16, 8));
+        assert_eq!(loaded.board.particles.len(), 20);
+        assert_eq!(loaded.buffer.len(), 16 * 8 * 4);
+    }
+
+    #[test]
+    fn corrupt_lengths_are_refused() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("run.checkpoint");
+        let (saved, state) = checkpoint(&path);
+        // After the size, the random number generator, the field key and `targets_outdated`.
+        let attractor_count = state + 8 + 32 + 8 + 16 + 8 + 1;
+        // After the static field, the blend flag and the next particle id, as there are no
+        // attractors.
+        let particle_count = attractor_count + 8 + 16 * 8 * 8 + 1 + 8;
+        let buffer_length = saved.len() - 16 * 8 * 4 - 8;
+        let read =
+            |offset: usize| u64::from_le_bytes(saved[offset..offset + 8].try_into().unwrap());
+        assert_eq!(read(attractor_count), 0);
+        assert_eq!(read(particle_count), 20);
+        assert_eq!(read(buffer_length), 16 * 8 * 4);
+
+        let huge = u64::MAX.to_le_bytes();
+        for (offset, value) in [
+            (CONFIG_LENGTH_OFFSET, &huge[..]),
+            (state, &u32::MAX.to_le_bytes()[..]),
+            (state, &0u32.to_le_bytes()[..]),
+            (attractor_count, &huge[..]),
+            (particle_count, &huge[..]),
+            (buffer_length, &huge[..]),
+            (buffer_length, &(16u64 * 8 * 4 + 1).to_le_bytes()[..]),
+        ] {
+            let result = load_corrupt(&path, &mut saved.clone(), offset, value);
+            assert!(
+                matches!(result, Err(Error::Decode { .. })),
+                "{:?} at offset {} was not refused",
+                value,
+                offset
+            );
+        }
+    }
+
+    /// Draws 4 frames of 32x32 pixels into `directory`: a disc moving to the right and growing,
+    /// so that particles keep being added.
+    fn synthetic_frames(directory: &Path) -> Vec<Frame> {
+        (0..4)
+            .map(|index| {
+                let (center, radius) = (8.0 + 5.0 * index as f32, 4.0 + 2.0 * index as f32);
+                let image = GrayImage::from_fn(32, 32, |x, y| {
+                    let (dx, dy) = (x as f32 - center, y as f32 - 16.0);
+                    Luma([if dx.hypot(dy) <= radius { 255 } else { 0 }])
+                });
+                let path = directory.join(format!("frame.{}.png", index));
+                image.save(&path).unwrap();
+                Frame::File(path)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn resumed_runs_render_the_same_frames() {
+        let directory = tempfile::tempdir().unwrap();
+        let frames = synthetic_frames(directory.path());
+        // Two output frames per input frame, so that the checkpoint falls in the middle of a
+        // field blend, with particles added and removed and trails drawn over the last frame.
+        let mut config = Config {
+            seed: Some(5),
+            sequence: SequenceConfig {
+                input_fps: FrameRate::from(4),
+                output_fps: FrameRate::from(8),
+                end_frames: 2,
+                pixels_per_particle: 16,
+                interpolation: Interpolation::Linear,
+                ..SequenceConfig::default()
+            },
+            ..Config::default()
+        };
+        config.population.control = true;
+        config.population.max_rate = 1;
+        config.render.style = RenderStyle::Trails;
+        const CHECKPOINT_FRAME: u64 = 5;
+
+        let straight = directory.path().join("straight");
+        let resumed = directory.path().join("resumed");
+        std::fs::create_dir(&straight).unwrap();
+        std::fs::create_dir(&resumed).unwrap();
+        let checkpoint_path = directory.path().join("run.checkpoint");
+
+        let mut board = seeded_board(&frames[0], &config, 16).unwrap();
+        let mut buffer = render::render_buffer(&board, &config);
+        let mut playback = Playback::new(frames.clone(), &config);
+        render::render_sequence(
+            &mut playback,
+            &mut board,
+            &mut buffer,
+            &config,
+            &straight,
+            0,
+            |_, _| (),
+            |output_frame, playback, board, buffer| {
+                if output_frame == CHECKPOINT_FRAME {
+                    save(
+                        &checkpoint_path,
+                        &config,
+                        4,
+                        output_frame,
+                        playback,
+                        board,
+                        buffer,
+                    )
+                    .unwrap();
+                }
+            },
+        )
+        .unwrap();
+
+        let mut checkpoint = load(&checkpoint_path).unwrap();
+        assert_eq!(checkpoint.output_frame, CHECKPOINT_FRAME);
+        let mut playback = Playback::new(frames, &checkpoint.config);
+        let (step, input) = checkpoint.position;
+        playback.resume_at(step, input);
+        render::render_sequence(
+            &mut playback,
+            &mut checkpoint.board,
+            &mut checkpoint.buffer,
+            &checkpoint.config,
+            &resumed,
+            checkpoint.output_frame,
+            |_, _| (),
+            |_, _, _, _| (),
+        )
+        .unwrap();
+
+        let output_frames = playback.output_frames();
+        assert!(CHECKPOINT_FRAME < output_frames);
+        for frame in 1..=output_frames {
+            let name = format!("render.{:0>2}.png", frame);
+            let resumed_frame = std::fs::read(resumed.join(&name));
+            if frame <= CHECKPOINT_FRAME {
+                assert!(resumed_frame.is_err(), "{} was rendered again", name);
+            } else {
+                let straight_frame = std::fs::read(straight.join(&name)).unwrap();
+                assert!(resumed_frame.unwrap() == straight_frame, "{} differs", name);
+            }
+        }
+    }
+}
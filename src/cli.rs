@@ -1,4 +1,14 @@
-use clap::{command, Parser, Subcommand};
+use std::path::PathBuf;
+
+use clap::{ArgAction, Args, Parser, Subcommand, ValueEnum};
+
+use physics_apple::{
+    config::{Channel, Extractor},
+    procedural::Procedural,
+    trajectories::TrajectoryFormat,
+};
+
+use crate::tui::TuiMode;
 
 /// A program to generate a particle-based simulation. You can exit with ESC or Q.
 #[derive(Parser, Debug)]
@@ -6,6 +16,74 @@ use clap::{command, Parser, Subcommand};
 pub struct CLIArgs {
     #[command(subcommand)]
     pub command: Commands,
+
+    /// Start from a named set of parameters. See the `presets` command for the available ones.
+    #[arg(short, long, global = true)]
+    pub preset: Option<String>,
+
+    /// Directory with user presets, stored as `<name>.toml`.
+    #[arg(long, global = true, default_value = "./presets")]
+    pub preset_dir: PathBuf,
+
+    /// TOML file with simulation and rendering parameters. Any parameter left out keeps its
+    /// default value (or the preset's). See `physics-apple.toml` for all of them.
+    #[arg(short, long, global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Override a single parameter, e.g. `--set physics.g=0.002`. Can be repeated, and takes
+    /// precedence over the config file.
+    #[arg(long = "set", value_name = "KEY=VALUE", global = true)]
+    pub overrides: Vec<String>,
+
+    /// How images are turned into attractors, e.g. `canny` to make particles trace outlines.
+    /// Shorthand for `--set attractors.extractor=NAME`.
+    #[arg(short, long, value_enum, value_name = "NAME", global = true)]
+    pub extractor: Option<Extractor>,
+
+    /// Which channel of the images the attractors are taken from. Shorthand for
+    /// `--set attractors.channel=CHANNEL`.
+    #[arg(long, value_enum, global = true)]
+    pub channel: Option<Channel>,
+
+    /// Show more messages: `-v` adds details and how long each stage (load, field, simulate,
+    /// render, encode) takes, `-vv` everything.
+    #[arg(short, long, action = ArgAction::Count, global = true, conflicts_with = "quiet")]
+    pub verbose: u8,
+
+    /// Show fewer messages: `-q` only warnings and errors, `-qq` only errors.
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    pub quiet: u8,
+
+    /// How messages are written to the standard error.
+    #[arg(
+        long,
+        value_enum,
+        value_name = "FORMAT",
+        global = true,
+        default_value_t
+    )]
+    pub log_format: LogFormat,
+
+    /// Once the command is done, print how long each stage took, how many steps and particle
+    /// updates were simulated per second, and the peak memory use. With a path, also save them
+    /// there as JSON (`--profile-report=report.json`), e.g. to track them over time.
+    #[arg(
+        long,
+        value_name = "JSON",
+        num_args = 0..=1,
+        require_equals = true,
+        global = true
+    )]
+    pub profile_report: Option<Option<PathBuf>>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum LogFormat {
+    /// One readable line per message.
+    #[default]
+    Text,
+    /// One JSON object per message, for other programs to read.
+    Json,
 }
 
 #[derive(Debug, Subcommand)]
@@ -13,8 +91,8 @@ pub enum Commands {
     /// Generate the static field for an entire directory.
     #[command(arg_required_else_help = true)]
     Generate {
-        /// Path to directory containing the desired files. Program will only generate files with the
-        /// .png extension.
+        /// Path to directory containing the desired files. Program will only generate files with an
+        /// image extension (.png, .jpg, .bmp, .webp, .tiff or .gif). Can also be a single file.
         path: String,
         /// How many threads to use. Has no effect if processing is done on the GPU. Default: Maximum
         #[arg(short, long)]
@@ -29,25 +107,187 @@ pub enum Commands {
     ViewField {
         /// The path to the file you want to see the static field.
         file: String,
+
+        /// Show the attractors instead of the field, to preview the extractor. Brighter pixels
+        /// have a larger mass, repelling pixels are red.
+        #[arg(short, long)]
+        attractors: bool,
+
+        /// Draw in the terminal instead of opening a window, for example over SSH. Pixels are
+        /// drawn with half-block characters, or with braille dots if MODE is 'braille'.
+        #[arg(long, value_enum, value_name = "MODE", num_args = 0..=1, default_missing_value = "half-block")]
+        tui: Option<TuiMode>,
     },
 
     /// Simulate a single file.
     #[command(arg_required_else_help = true)]
     SimulateFile {
-        /// The file to base the simulation on.
+        /// The file to base the simulation on: an image, an SVG file, or a GIF (of which the
+        /// first frame is used).
         file: String,
+
+        #[command(flatten)]
+        options: SimulateOptions,
     },
 
+    /// Simulate particles forming a text, drawn in the bundled font.
+    #[command(arg_required_else_help = true)]
+    SimulateText {
+        /// The text to form. Use a line break (e.g. `$'HELLO\nWORLD'` in bash) for several lines.
+        text: String,
+
+        #[command(flatten)]
+        options: SimulateOptions,
+    },
+
+    /// List the available presets.
+    Presets,
+
     /// Simulate a sequence of files from a directory by their alphabetical order. Make sure the
     /// files have leading zeros whe numbered.
     #[command(arg_required_else_help = true)]
     SimulateSequence {
-        /// Path to directory containing the desired files. Program will simulate files with the
-        /// .field extension.
-        path: String,
+        /// Path to directory containing the desired files, or an animated GIF. Fields of the
+        /// images are loaded from their .field files if these exist.
+        #[arg(required_unless_present = "procedural")]
+        path: Option<String>,
+
+        /// Simulate a generated animation instead of files. Nothing is read from or written to
+        /// disk besides the saved frames.
+        #[arg(long, value_enum, value_name = "NAME", conflicts_with = "path")]
+        procedural: Option<Procedural>,
+
+        /// Length of the procedural animation, in input frames.
+        #[arg(long, default_value_t = 120, requires = "procedural", value_parser = clap::value_parser!(u32).range(1..))]
+        frames: u32,
 
         /// Enable saving the simulation to a file.
         #[arg(short, long)]
         save_to_file: bool,
+
+        /// How many times larger the saved frames are than the simulation. Particles are drawn
+        /// at their sub-pixel position, so upscaled frames stay smooth. Only used together with
+        /// --save-to-file. Shorthand for `--set render.render_scale=N`.
+        #[arg(short, long, value_parser = clap::value_parser!(u32).range(1..))]
+        render_scale: Option<u32>,
+
+        /// Seed used to place the particles. Runs with the same seed give the same result.
+        /// Shorthand for `--set seed=N`.
+        #[arg(long)]
+        seed: Option<u64>,
+
+        /// Save a checkpoint every N output frames, so that a long run can be continued with
+        /// --resume-from if it is interrupted. Only used together with --save-to-file.
+        #[arg(long, value_name = "N", requires = "save_to_file", value_parser = clap::value_parser!(u64).range(1..))]
+        checkpoint_every: Option<u64>,
+
+        /// Where checkpoints are written.
+        #[arg(long, value_name = "FILE", default_value = "./render/checkpoint.bin")]
+        checkpoint: PathBuf,
+
+        /// Continue a run from a checkpoint, giving the same frames it was started with. The
+        /// configuration saved in the checkpoint is used, so it cannot be combined with the
+        /// options that change it, and the remaining frames are identical to those of an
+        /// uninterrupted run.
+        #[arg(long, value_name = "FILE", requires = "save_to_file")]
+        resume_from: Option<PathBuf>,
+
+        #[command(flatten)]
+        export: ExportOptions,
+
+        /// Draw in the terminal instead of opening a window, for example over SSH. Cannot be
+        /// used with --save-to-file. Pixels are drawn with half-block characters, or with braille
+        /// dots if MODE is 'braille'.
+        #[arg(
+            long,
+            value_enum,
+            value_name = "MODE",
+            num_args = 0..=1,
+            default_missing_value = "half-block",
+            conflicts_with = "save_to_file"
+        )]
+        tui: Option<TuiMode>,
     },
 }
+
+/// Options shared by the commands that simulate a single image.
+#[derive(Args, Debug)]
+pub struct SimulateOptions {
+    /// Run without a window for a fixed number of steps and save the result as PNG images.
+    /// Unlike the live view, the result does not depend on the speed of the machine.
+    #[arg(long, conflicts_with = "tui")]
+    pub headless: bool,
+
+    /// How many simulation steps to run in headless mode.
+    #[arg(long, default_value_t = 1000, requires = "headless")]
+    pub steps: u32,
+
+    /// Also save a snapshot every N steps in headless mode. The final image is always saved.
+    #[arg(long, value_name = "N", requires = "headless", value_parser = clap::value_parser!(u32).range(1..))]
+    pub snapshot_every: Option<u32>,
+
+    /// Directory the headless images are saved to.
+    #[arg(short, long, default_value = "./render", requires = "headless")]
+    pub output: PathBuf,
+
+    /// How many times larger the headless images are than the simulation. Shorthand for
+    /// `--set render.render_scale=N`.
+    #[arg(short, long, requires = "headless", value_parser = clap::value_parser!(u32).range(1..))]
+    pub render_scale: Option<u32>,
+
+    /// Seed used to place the particles. Runs with the same seed give the same result.
+    /// Shorthand for `--set seed=N`.
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Draw in the terminal instead of opening a window, for example over SSH. Pixels are
+    /// drawn with half-block characters, or with braille dots if MODE is 'braille'.
+    #[arg(long, value_enum, value_name = "MODE", num_args = 0..=1, default_missing_value = "half-block")]
+    pub tui: Option<TuiMode>,
+}
+
+/// Options for exporting the trajectories of the particles of a saved sequence.
+#[derive(Args, Debug)]
+pub struct ExportOptions {
+    /// Write the position and velocity of every particle at each saved frame to FILE, e.g. to
+    /// post-process them in Blender or plot them in a notebook. Only used together with
+    /// --save-to-file. Cannot be used with --resume-from, as the frames exported before the
+    /// interruption would be lost.
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "save_to_file",
+        conflicts_with = "resume_from"
+    )]
+    pub export_trajectories: Option<PathBuf>,
+
+    /// Format of the exported trajectories. Defaults to the extension of the file: csv, jsonl,
+    /// or binary for anything else.
+    #[arg(
+        long,
+        value_enum,
+        value_name = "FORMAT",
+        requires = "export_trajectories"
+    )]
+    pub export_format: Option<TrajectoryFormat>,
+
+    /// Only export every Nth saved frame.
+    #[arg(long, value_name = "N", default_value_t = 1, requires = "export_trajectories", value_parser = clap::value_parser!(u64).range(1..))]
+    pub export_stride: u64,
+
+    /// Only export this fraction (0 to 1) of the particles. The same particles are exported in
+    /// every frame.
+    #[arg(long, value_name = "FRACTION", default_value_t = 1.0, requires = "export_trajectories", value_parser = parse_fraction)]
+    pub export_sample: f64,
+}
+
+fn parse_fraction(value: &str) -> Result<f64, String> {
+    let fraction: f64 = value
+        .parse()
+        .map_err(|_| format!("'{}' is not a number", value))?;
+    if fraction > 0.0 && fraction <= 1.0 {
+        Ok(fraction)
+    } else {
+        Err("must be above 0 and at most 1".to_string())
+    }
+}
@@ -0,0 +1,962 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Every tunable parameter of the simulation and the renderer. It is built from the defaults
+/// below, then a preset passed with `--preset`, then a TOML file passed with `--config`, then
+/// single keys passed with `--set`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Seed used for everything random. When missing, a random seed is picked and written to the
+    /// effective config, so the run can still be reproduced.
+    pub seed: Option<u64>,
+    pub simulation: SimulationConfig,
+    pub sequence: SequenceConfig,
+    pub cuts: CutConfig,
+    pub population: PopulationConfig,
+    pub attractors: AttractorConfig,
+    pub physics: PhysicsConfig,
+    pub spawn: SpawnConfig,
+    pub render: RenderConfig,
+}
+
+/// Parameters of `simulate-file` and `view-field`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SimulationConfig {
+    /// Run as many updates as fit in a frame of the live view, at `fps` frames per second.
+    pub use_fps: bool,
+    pub fps: u32,
+    /// Otherwise, if set, run exactly `iter_per_frame` updates per frame. If neither is set, a
+    /// single update is run per frame.
+    pub use_fixed_iter: bool,
+    pub iter_per_frame: u32,
+    /// One particle is spawned for this many pixels of the board.
+    pub pixels_per_particle: u32,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        Self {
+            use_fps: true,
+            fps: 30,
+            use_fixed_iter: false,
+            iter_per_frame: 100,
+            pixels_per_particle: 8,
+        }
+    }
+}
+
+/// Parameters of `simulate-sequence`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SequenceConfig {
+    /// Frame rate of the input frames, i.e. of the original video.
+    pub input_fps: FrameRate,
+    /// Frame rate of the saved frames and of the live view. It doesn't have to be a multiple of
+    /// `input_fps`: at 24 fps in and 60 fps out, input frames are shown for 2 and 3 output frames
+    /// in turn.
+    pub output_fps: FrameRate,
+    /// How many updates to run per second of video. Together with `physics.timestep`, this sets
+    /// how fast particles move compared to the video.
+    pub steps_per_second: u32,
+    /// How many frames to keep the simulation going after the last input frame has been
+    /// simulated.
+    pub end_frames: u32,
+    /// One particle is spawned for this many pixels of the board.
+    pub pixels_per_particle: u32,
+    /// How the field changes from one input frame to the next.
+    pub interpolation: Interpolation,
+}
+
+impl Default for SequenceConfig {
+    fn default() -> Self {
+        Self {
+            input_fps: FrameRate::from(30),
+            output_fps: FrameRate::from(30),
+            steps_per_second: 600,
+            end_frames: 48 * 5,
+            pixels_per_particle: 16,
+            interpolation: Interpolation::default(),
+        }
+    }
+}
+
+/// A number of frames per second, kept as an exact fraction so that rates like NTSC's 30000/1001
+/// don't drift over a long video. Written either as a number (`30`, `12.5`) or as a fraction in a
+/// string (`"30000/1001"`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "RawFrameRate", into = "RawFrameRate")]
+pub struct FrameRate {
+    pub numerator: u64,
+    pub denominator: u64,
+}
+
+impl FrameRate {
+    /// How long after the start frame `frame` is shown.
+    pub fn time_of(&self, frame: u64) -> Duration {
+        let nanos =
+            frame as u128 * self.denominator as u128 * 1_000_000_000 / self.numerator as u128;
+        Duration::from_nanos(nanos as u64)
+    }
+}
+
+impl From<u64> for FrameRate {
+    fn from(fps: u64) -> Self {
+        Self {
+            numerator: fps,
+            denominator: 1,
+        }
+    }
+}
+
+impl fmt::Display for FrameRate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.denominator == 1 {
+            write!(f, "{}", self.numerator)
+        } else {
+            write!(f, "{}/{}", self.numerator, self.denominator)
+        }
+    }
+}
+
+/// A frame rate as it is written in TOML.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum RawFrameRate {
+    Integer(u64),
+    Float(f64),
+    Fraction(String),
+}
+
+impl TryFrom<RawFrameRate> for FrameRate {
+    type Error = String;
+
+    fn try_from(raw: RawFrameRate) -> Result<Self, Self::Error> {
+        let (numerator, denominator) = match raw {
+            RawFrameRate::Integer(fps) => (fps, 1),
+            // Enough precision for any real frame rate, exact ones should be given as fractions.
+            RawFrameRate::Float(fps) if fps.is_finite() && fps > 0.0 => {
+                ((fps * 1000.0).round() as u64, 1000)
+            }
+            RawFrameRate::Float(fps) => return Err(format!("invalid frame rate {}", fps)),
+            RawFrameRate::Fraction(fraction) => {
+                let parse = |part: &str| part.trim().parse::<u64>().ok();
+                fraction
+                    .split_once('/')
+                    .and_then(|(numerator, denominator)| {
+                        Some((parse(numerator)?, parse(denominator)?))
+                    })
+                    .ok_or_else(|| {
+                        format!(
+                            "invalid frame rate '{}', expected e.g. \"30000/1001\"",
+                            fraction
+                        )
+                    })?
+            }
+        };
+        if numerator == 0 || denominator == 0 {
+            return Err("frame rates must be positive".to_string());
+        }
+
+        let divisor = gcd(numerator, denominator);
+        Ok(FrameRate {
+            numerator: numerator / divisor,
+            denominator: denominator / divisor,
+        })
+    }
+}
+
+impl From<FrameRate> for RawFrameRate {
+    fn from(rate: FrameRate) -> Self {
+        if rate.denominator == 1 {
+            RawFrameRate::Integer(rate.numerator)
+        } else {
+            RawFrameRate::Fraction(rate.to_string())
+        }
+    }
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Interpolation {
+    /// The field of the next frame replaces the previous one at once.
+    #[default]
+    None,
+    /// The field blends from the previous frame to the next one at a constant rate, over all
+    /// updates the next frame is shown for.
+    Linear,
+    /// Like `linear`, but the blend starts and ends slowly (smoothstep).
+    EaseInOut,
+}
+
+impl Interpolation {
+    /// Maps how far (0 to 1) a blend is in time to how far it is between both fields.
+    pub fn apply(&self, progress: f32) -> f32 {
+        let progress = progress.clamp(0.0, 1.0);
+        match self {
+            Interpolation::None => 1.0,
+            Interpolation::Linear => progress,
+            Interpolation::EaseInOut => progress * progress * (3.0 - 2.0 * progress),
+        }
+    }
+}
+
+/// Detection of hard cuts between shots in `simulate-sequence`, and what happens to the particles
+/// on a cut.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CutConfig {
+    pub policy: CutPolicy,
+    /// Overlap (intersection over union) of the attractors of two consecutive frames below which
+    /// the second one starts a new shot.
+    pub threshold: f32,
+    /// Speed particles fly apart at with the `explode` policy.
+    pub explode_speed: f32,
+}
+
+impl Default for CutConfig {
+    fn default() -> Self {
+        Self {
+            policy: CutPolicy::default(),
+            threshold: 0.2,
+            explode_speed: 0.3,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CutPolicy {
+    /// Cuts are not detected, particles migrate to the new image on their own.
+    #[default]
+    None,
+    /// Particles are removed and spawned again, as at the start of the simulation.
+    Respawn,
+    /// Every particle jumps onto a random attractor of the new frame.
+    Teleport,
+    /// Particles fly apart from the center of the previous image, then fall into the new one.
+    Explode,
+}
+
+/// Control of the number of particles in `simulate-sequence`, so that frames with few attractors
+/// aren't overcrowded and frames with many don't look sparse.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PopulationConfig {
+    /// Add and remove particles over time, instead of keeping those spawned at the start.
+    pub control: bool,
+    /// How many particles to aim for per attracting pixel of the current frame.
+    pub ratio: f32,
+    /// Most particles added or removed per update.
+    pub max_rate: u32,
+    /// How many updates new particles take to fade in, and removed ones to fade out.
+    pub fade_steps: u32,
+}
+
+impl Default for PopulationConfig {
+    fn default() -> Self {
+        Self {
+            control: false,
+            ratio: 0.5,
+            max_rate: 2,
+            fade_steps: 60,
+        }
+    }
+}
+
+/// How the pixels of an image are turned into attractors.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AttractorConfig {
+    /// Which channel of the image the attractors are taken from.
+    pub channel: Channel,
+    /// What the transparency of the image means.
+    pub alpha: AlphaMode,
+    /// How the channel is processed before it is turned into attractors.
+    pub extractor: Extractor,
+    pub mode: AttractorMode,
+    /// Brightness, between 0 and 1, a pixel must exceed to become an attractor.
+    pub threshold: f32,
+    /// In `luminance` mode, the exponent of the transfer curve from brightness to mass. Values
+    /// above 1 favour the brightest pixels, values below 1 give dim pixels more weight.
+    pub gamma: f32,
+    /// How strongly pixels darker than `dark_threshold` push particles away, as a fraction of
+    /// `physics.attractor_mass`. 0 (the default) leaves dark pixels out of the field entirely.
+    pub repulsion: f32,
+    /// Brightness, between 0 and 1, a pixel must stay below to repel particles.
+    pub dark_threshold: f32,
+}
+
+impl Default for AttractorConfig {
+    fn default() -> Self {
+        Self {
+            channel: Channel::default(),
+            alpha: AlphaMode::default(),
+            extractor: Extractor::default(),
+            mode: AttractorMode::default(),
+            threshold: 0.5,
+            gamma: 1.0,
+            repulsion: 0.0,
+            dark_threshold: 0.5,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum Channel {
+    /// The brightness of the pixel.
+    #[default]
+    Luma,
+    Red,
+    Green,
+    Blue,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AlphaMode {
+    /// Pixels that are at most half opaque count as black, the others keep their colour, as
+    /// images always were.
+    #[default]
+    Threshold,
+    /// The transparency is thrown away and only the colours are used.
+    Ignore,
+    /// The image is laid over black: transparent pixels count as black, and translucent ones
+    /// are darker and get less mass.
+    Mass,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum Extractor {
+    /// Use the channel as it is: bright pixels attract.
+    #[default]
+    Plain,
+    /// Dark pixels attract instead of bright ones.
+    Inverted,
+    /// Pick the threshold between dark and bright per frame, with Otsu's method.
+    Otsu,
+    /// Strength of the edges (Sobel operator), so particles trace outlines.
+    Sobel,
+    /// Thin, connected edges (Canny edge detector).
+    Canny,
+    /// The one pixel wide center lines of the bright shapes.
+    Skeleton,
+    /// Distance to the edge of the bright shapes, so particles gather in their middle.
+    Distance,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AttractorMode {
+    /// Every pixel brighter than the threshold gets the full `physics.attractor_mass` (and every
+    /// dark pixel the full repulsion). With the other defaults, these are the pixels that always
+    /// attracted: more than half white and more than half opaque.
+    #[default]
+    Threshold,
+    /// Pixels brighter than the threshold get a mass that grows with their brightness, up to
+    /// `physics.attractor_mass` for white. Keeps gradients and anti-aliased edges. Likewise, the
+    /// repulsion of dark pixels grows with their darkness.
+    Luminance,
+}
+
+impl AttractorConfig {
+    /// The fraction of `physics.attractor_mass` given to a pixel of brightness `value` (0-255),
+    /// or `None` if it does not take part in the field. Repelling pixels get a negative weight.
+    pub fn weight(&self, value: u8) -> Option<f32> {
+        let brightness = value as f32 / 255.0;
+        if brightness > self.threshold {
+            Some(self.curve((brightness - self.threshold) / (1.0 - self.threshold)))
+        } else if self.repulsion > 0.0 && brightness < self.dark_threshold {
+            let darkness = (self.dark_threshold - brightness) / self.dark_threshold;
+            Some(-self.repulsion * self.curve(darkness))
+        } else {
+            None
+        }
+    }
+
+    /// Maps how far (0 to 1) a pixel is past its threshold to the fraction of the mass it gets.
+    fn curve(&self, amount: f32) -> f32 {
+        match self.mode {
+            AttractorMode::Threshold => 1.0,
+            AttractorMode::Luminance => amount.powf(self.gamma),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PhysicsConfig {
+    pub attractor_mass: f32,
+    pub particle_mass: f32,
+    /// How much simulation time passes with each update.
+    pub timestep: f32,
+    /// The gravitational constant. We'll set this constant however we want, as to fine-tune our
+    /// simulation.
+    pub g: f32,
+    /// How quickly the attraction fades with distance.
+    pub force_law: ForceLaw,
+    /// Fraction of its velocity a particle loses per unit of simulation time. Higher values make
+    /// particles settle instead of overshooting the attractors.
+    pub drag: f32,
+    /// What moves particles to the image.
+    pub mode: PhysicsMode,
+    /// In `assignment` mode, the strength of the spring pulling each particle to its target.
+    pub spring: f32,
+    /// In `assignment` mode, how much the spring slows particles down, so that they settle on
+    /// their target instead of swinging around it.
+    pub spring_damping: f32,
+    /// In `assignment` mode, how much of the static field still applies, from 0 (none) to 1.
+    pub field_weight: f32,
+    /// In `assignment` mode, how hard to look for a better matching between particles and
+    /// targets. Each pass tries as many swaps as there are particles.
+    pub assignment_passes: u32,
+}
+
+impl Default for PhysicsConfig {
+    fn default() -> Self {
+        Self {
+            attractor_mass: 10.0,
+            particle_mass: 1.0,
+            timestep: 1.0,
+            g: 1.0 / 1000.0,
+            force_law: ForceLaw::default(),
+            drag: 0.0,
+            mode: PhysicsMode::default(),
+            spring: 0.002,
+            spring_damping: 0.05,
+            field_weight: 0.2,
+            assignment_passes: 20,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PhysicsMode {
+    /// Particles fall into the static field of the attractors. They gather where the mass is, and
+    /// can leave some parts of the image empty.
+    #[default]
+    Gravity,
+    /// Every particle is matched to its own target pixel, spreading them evenly over the image,
+    /// and pulled there by a spring on top of a weaker static field. The matching keeps the
+    /// distance particles travel short, and is updated from the previous one on every new frame.
+    Assignment,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ForceLaw {
+    /// F ~ 1/r^3. What the simulation has always used: very local, so particles stick to the
+    /// closest attractors.
+    #[default]
+    InverseCube,
+    /// F ~ 1/r^2, Newton's law of gravity.
+    InverseSquare,
+    /// F ~ 1/r, gravity in a two-dimensional world. Far away attractors still pull noticeably.
+    Inverse,
+}
+
+impl ForceLaw {
+    /// The power of the distance the force is divided by.
+    pub fn exponent(&self) -> i32 {
+        match self {
+            ForceLaw::InverseCube => 3,
+            ForceLaw::InverseSquare => 2,
+            ForceLaw::Inverse => 1,
+        }
+    }
+}
+
+/// How particles are placed on the board when a simulation starts.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SpawnConfig {
+    pub mode: SpawnMode,
+    /// Initial speed of particles in `orbit` mode.
+    pub orbit_speed: f32,
+}
+
+impl Default for SpawnConfig {
+    fn default() -> Self {
+        Self {
+            mode: SpawnMode::default(),
+            orbit_speed: 0.5,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SpawnMode {
+    /// Particles are scattered randomly and start at rest.
+    #[default]
+    Random,
+    /// Particles start at rest on an evenly spaced grid.
+    Grid,
+    /// Particles are scattered randomly and start circling the center of the board.
+    Orbit,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RenderConfig {
+    /// How much larger the window is than the board.
+    pub window_scale: f64,
+    /// How many times larger saved images are than the board.
+    pub render_scale: u32,
+    /// How far a particle reaches on upscaled images, in image pixels. Defaults to
+    /// `render_scale`, which keeps the brightness close to that of an image at the board's
+    /// resolution.
+    pub splat_radius: Option<f32>,
+    pub style: RenderStyle,
+    /// In `density` style, how many particles it takes for a pixel to be fully white.
+    pub density_saturation: f32,
+    /// In `trails` style, how much of the previous frame is kept in the next one.
+    pub trail_decay: f32,
+}
+
+impl Default for RenderConfig {
+    fn default() -> Self {
+        Self {
+            window_scale: 2.0,
+            render_scale: 1,
+            splat_radius: None,
+            style: RenderStyle::default(),
+            density_saturation: 4.0,
+            trail_decay: 0.8,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RenderStyle {
+    /// Every pixel holding a particle is white.
+    #[default]
+    Solid,
+    /// Pixels get brighter the more particles they hold.
+    Density,
+    /// Like `solid`, but particles leave a fading trail behind.
+    Trails,
+}
+
+impl Config {
+    /// Builds the configuration from the defaults, the optional `preset`, the optional TOML
+    /// `file` and the `overrides`, in this order. Each override has the form
+    /// `section.key=value`, where the value is written as in TOML (strings may leave out the
+    /// quotes).
+    ///
+    /// Fails with `Error::Io` if `file` cannot be read, and with `Error::Config` if any of them is
+    /// invalid.
+    pub fn load(
+        preset: Option<&Preset>,
+        file: Option<&Path>,
+        overrides: &[String],
+    ) -> crate::Result<Config> {
+        let mut table = toml::Table::try_from(Config::default())
+            .map_err(|err| crate::Error::Config(err.to_string()))?;
+
+        if let Some(preset) = preset {
+            let preset_table: toml::Table = toml::from_str(&preset.contents).map_err(|err| {
+                crate::Error::Config(format!("Invalid preset '{}': {}", preset.name, err))
+            })?;
+            merge_tables(&mut table, preset_table);
+        }
+
+        if let Some(file) = file {
+            let contents =
+                std::fs::read_to_string(file).map_err(|err| crate::Error::io(file, err))?;
+            let file_table: toml::Table = toml::from_str(&contents).map_err(|err| {
+                crate::Error::Config(format!("Invalid config '{}': {}", file.display(), err))
+            })?;
+            merge_tables(&mut table, file_table);
+        }
+
+        for assignment in overrides {
+            apply_override(&mut table, assignment).map_err(crate::Error::Config)?;
+        }
+
+        let config: Config = table
+            .try_into()
+            .map_err(|err| crate::Error::Config(format!("Invalid configuration: {}", err)))?;
+        config.validate().map_err(crate::Error::Config)?;
+
+        Ok(config)
+    }
+
+    /// Checks that the parameters make sense together, e.g. that there is at least one update
+    /// per frame and that masses are positive.
+    pub fn validate(&self) -> Result<(), String> {
+        let mut problems = vec![];
+        let mut check = |valid: bool, problem: &str| {
+            if !valid {
+                problems.push(problem.to_string());
+            }
+        };
+
+        // TOML integers are signed, larger seeds could not be written to the effective config.
+        check(
+            self.seed.is_none_or(|seed| seed <= i64::MAX as u64),
+            "seed must be at most 9223372036854775807",
+        );
+        check(self.simulation.fps > 0, "simulation.fps must be at least 1");
+        check(
+            self.simulation.iter_per_frame > 0,
+            "simulation.iter_per_frame must be at least 1",
+        );
+        check(
+            self.simulation.pixels_per_particle > 0,
+            "simulation.pixels_per_particle must be at least 1",
+        );
+        check(
+            self.sequence.steps_per_second > 0,
+            "sequence.steps_per_second must be at least 1",
+        );
+        check(
+            self.sequence.pixels_per_particle > 0,
+            "sequence.pixels_per_particle must be at least 1",
+        );
+        check(
+            (0.0..=1.0).contains(&self.cuts.threshold),
+            "cuts.threshold must be between 0 and 1",
+        );
+        check(
+            self.cuts.explode_speed.is_finite() && self.cuts.explode_speed >= 0.0,
+            "cuts.explode_speed must not be negative",
+        );
+        check(
+            self.population.ratio.is_finite() && self.population.ratio >= 0.0,
+            "population.ratio must not be negative",
+        );
+        check(
+            self.population.max_rate > 0,
+            "population.max_rate must be at least 1",
+        );
+        check(
+            (0.0..1.0).contains(&self.attractors.threshold),
+            "attractors.threshold must be between 0 (inclusive) and 1 (exclusive)",
+        );
+        check(
+            is_positive(self.attractors.gamma),
+            "attractors.gamma must be a positive number",
+        );
+        check(
+            self.attractors.repulsion.is_finite() && self.attractors.repulsion >= 0.0,
+            "attractors.repulsion must not be negative",
+        );
+        // Without repulsion the dark threshold is unused, and must not get in the way of a low
+        // threshold.
+        check(
+            self.attractors.repulsion == 0.0
+                || (self.attractors.dark_threshold > 0.0
+                    && self.attractors.dark_threshold <= self.attractors.threshold),
+            "attractors.dark_threshold must be above 0 and at most attractors.threshold when \
+             attractors.repulsion is used",
+        );
+        check(
+            is_positive(self.physics.attractor_mass),
+            "physics.attractor_mass must be a positive number",
+        );
+        check(
+            is_positive(self.physics.particle_mass),
+            "physics.particle_mass must be a positive number",
+        );
+        check(
+            is_positive(self.physics.timestep),
+            "physics.timestep must be a positive number",
+        );
+        check(
+            is_positive(self.physics.g),
+            "physics.g must be a positive number",
+        );
+        check(
+            self.physics.drag.is_finite() && self.physics.drag >= 0.0,
+            "physics.drag must not be negative",
+        );
+        check(
+            self.physics.spring.is_finite() && self.physics.spring >= 0.0,
+            "physics.spring must not be negative",
+        );
+        check(
+            self.physics.spring_damping.is_finite() && self.physics.spring_damping >= 0.0,
+            "physics.spring_damping must not be negative",
+        );
+        check(
+            (0.0..=1.0).contains(&self.physics.field_weight),
+            "physics.field_weight must be between 0 and 1",
+        );
+        check(
+            self.spawn.orbit_speed.is_finite(),
+            "spawn.orbit_speed must be a number",
+        );
+        check(
+            self.render.window_scale.is_finite() && self.render.window_scale > 0.0,
+            "render.window_scale must be a positive number",
+        );
+        check(
+            self.render.render_scale > 0,
+            "render.render_scale must be at least 1",
+        );
+        check(
+            self.render.splat_radius.is_none_or(is_positive),
+            "render.splat_radius must be a positive number",
+        );
+        check(
+            is_positive(self.render.density_saturation),
+            "render.density_saturation must be a positive number",
+        );
+        check(
+            (0.0..1.0).contains(&self.render.trail_decay),
+            "render.trail_decay must be between 0 (inclusive) and 1 (exclusive)",
+        );
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(format!(
+                "Invalid configuration:\n  {}",
+                problems.join("\n  ")
+            ))
+        }
+    }
+
+    /// Writes the configuration as TOML, so that a run can be repeated with `--config`.
+    pub fn save(&self, path: &Path) -> crate::Result<()> {
+        let text = toml::to_string_pretty(self).map_err(|err| crate::Error::Encode {
+            path: path.to_path_buf(),
+            message: err.to_string(),
+        })?;
+        std::fs::write(path, text).map_err(|err| crate::Error::io(path, err))
+    }
+
+    /// The radius used when drawing particles on upscaled images.
+    pub fn splat_radius(&self) -> f32 {
+        self.render
+            .splat_radius
+            .unwrap_or(self.render.render_scale as f32)
+    }
+}
+
+/// Presets shipped with the program, as (name, contents) pairs.
+const BUILTIN_PRESETS: [(&str, &str); 3] = [
+    (
+        "crisp-silhouette",
+        include_str!("presets/crisp-silhouette.toml"),
+    ),
+    (
+        "swirling-galaxy",
+        include_str!("presets/swirling-galaxy.toml"),
+    ),
+    ("dust-settle", include_str!("presets/dust-settle.toml")),
+];
+
+/// A named, partial configuration. Presets are either built in, or `<name>.toml` files in the
+/// preset directory. A preset in the directory takes the place of a built-in one with the same
+/// name.
+pub struct Preset {
+    pub name: String,
+    /// Where the preset comes from, or `None` if it is built in.
+    pub path: Option<PathBuf>,
+    pub contents: String,
+}
+
+impl Preset {
+    /// Looks up the preset called `name`, first in `directory`, then among the built-in ones.
+    /// Fails with `Error::Config` if there is none, or as `list` does.
+    pub fn find(name: &str, directory: &Path) -> crate::Result<Preset> {
+        Preset::list(directory)?
+            .into_iter()
+            .find(|preset| preset.name == name)
+            .ok_or_else(|| {
+                let names: Vec<String> = Preset::list(directory)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|preset| preset.name)
+                    .collect();
+                crate::Error::Config(format!(
+                    "Unknown preset '{}'. Available presets: {}",
+                    name,
+                    names.join(", ")
+                ))
+            })
+    }
+
+    /// Lists the built-in presets, followed by those in `directory` (if it exists). Fails with
+    /// `Error::Io` if the directory or a preset in it cannot be read.
+    pub fn list(directory: &Path) -> crate::Result<Vec<Preset>> {
+        let mut presets: Vec<Preset> = BUILTIN_PRESETS
+            .iter()
+            .map(|(name, contents)| Preset {
+                name: name.to_string(),
+                path: None,
+                contents: contents.to_string(),
+            })
+            .collect();
+
+        if !directory.is_dir() {
+            return Ok(presets);
+        }
+
+        let mut paths = vec![];
+        let io_error = |err| crate::Error::io(directory, err);
+        for entry in std::fs::read_dir(directory).map_err(io_error)? {
+            let path = entry.map_err(io_error)?.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "toml")
+            {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+
+        for path in paths {
+            let name = path.file_stem().unwrap().to_string_lossy().to_string();
+            let contents =
+                std::fs::read_to_string(&path).map_err(|err| crate::Error::io(&path, err))?;
+            presets.retain(|preset| preset.name != name);
+            presets.push(Preset {
+                name,
+                path: Some(path),
+                contents,
+            });
+        }
+
+        Ok(presets)
+    }
+
+    /// The first comment of the preset, which describes it.
+    pub fn description(&self) -> &str {
+        self.contents
+            .lines()
+            .find_map(|line| line.trim().strip_prefix('#'))
+            .map(str::trim)
+            .unwrap_or("")
+    }
+}
+
+fn is_positive(value: f32) -> bool {
+    value.is_finite() && value > 0.0
+}
+
+/// Recursively copies every key of `overlay` into `base`. Tables are merged, anything else is
+/// replaced.
+fn merge_tables(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base_table)), toml::Value::Table(overlay_table)) => {
+                merge_tables(base_table, overlay_table)
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+fn apply_override(table: &mut toml::Table, assignment: &str) -> Result<(), String> {
+    let (key, raw_value) = assignment
+        .split_once('=')
+        .ok_or_else(|| format!("Override '{}' is not of the form key=value", assignment))?;
+
+    // Parse the value the same way it would be parsed in the file. If that fails, the value is
+    // most likely a string without quotes.
+    let value = toml::from_str::<toml::Table>(&format!("value = {}", raw_value.trim()))
+        .ok()
+        .and_then(|mut parsed| parsed.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(raw_value.trim().to_string()));
+
+    let mut path: Vec<&str> = key.trim().split('.').collect();
+    let last = path.pop().filter(|last| !last.is_empty());
+    let last = last.ok_or_else(|| format!("Override '{}' has an empty key", assignment))?;
+
+    let mut current = table;
+    for section in path {
+        current = current
+            .entry(section)
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+            .as_table_mut()
+            .ok_or_else(|| format!("Override '{}': '{}' is not a section", assignment, section))?;
+    }
+    current.insert(last.to_string(), value);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_attractors(attractors: AttractorConfig) -> Config {
+        Config {
+            attractors,
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn unreadable_files_and_invalid_values_are_told_apart() {
+        let directory = tempfile::tempdir().unwrap();
+        let missing = directory.path().join("missing.toml");
+        assert!(matches!(
+            Config::load(None, Some(&missing), &[]),
+            Err(crate::Error::Io { .. })
+        ));
+
+        let invalid = directory.path().join("invalid.toml");
+        std::fs::write(&invalid, "[physics]\ng = \"strong\"\n").unwrap();
+        assert!(matches!(
+            Config::load(None, Some(&invalid), &[]),
+            Err(crate::Error::Config(_))
+        ));
+        assert!(matches!(
+            Config::load(None, None, &["physics.particle_mass=-1".to_string()]),
+            Err(crate::Error::Config(_))
+        ));
+        assert!(matches!(
+            Preset::find("no-such-preset", directory.path()),
+            Err(crate::Error::Config(_))
+        ));
+    }
+
+    #[test]
+    fn low_threshold_without_repulsion_is_valid() {
+        let config = with_attractors(AttractorConfig {
+            threshold: 0.3,
+            repulsion: 0.0,
+            ..AttractorConfig::default()
+        });
+        assert_eq!(config.validate(), Ok(()));
+    }
+
+    #[test]
+    fn dark_threshold_above_threshold_with_repulsion_is_invalid() {
+        let config = with_attractors(AttractorConfig {
+            threshold: 0.3,
+            repulsion: 0.5,
+            ..AttractorConfig::default()
+        });
+        assert!(config
+            .validate()
+            .unwrap_err()
+            .contains("attractors.dark_threshold"));
+    }
+}
@@ -0,0 +1,126 @@
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+/// Everything that can go wrong while loading frames, generating fields, simulating and
+/// rendering. Each kind has its own exit code in the CLI, see `exit_code`.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The configuration, a preset or the command line is invalid.
+    #[error("{0}")]
+    Config(String),
+    /// A file or directory could not be read or written.
+    #[error("could not access '{}': {source}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    /// A file was read, but is not what it should be: a broken image or SVG, a GIF with fewer
+    /// frames than expected, a checkpoint of another version...
+    #[error("could not decode '{}': {message}", path.display())]
+    Decode { path: PathBuf, message: String },
+    /// A frame does not have the size of the board it is simulated on.
+    #[error(
+        "'{frame}' is {}x{}, but the board is {}x{}",
+        actual.0,
+        actual.1,
+        expected.0,
+        expected.1
+    )]
+    DimensionMismatch {
+        frame: String,
+        expected: (u32, u32),
+        actual: (u32, u32),
+    },
+    /// A `.field` file exists, but is damaged. Fields generated from other parameters are not
+    /// corrupt, they are simply generated again.
+    #[error("the field '{}' is corrupt ({reason})", path.display())]
+    CorruptField { path: PathBuf, reason: String },
+    /// An image or other output could not be encoded.
+    #[error("could not encode '{}': {message}", path.display())]
+    Encode { path: PathBuf, message: String },
+    /// CUDA, the window or the terminal failed.
+    #[error("{backend} failed: {message}")]
+    Backend {
+        backend: &'static str,
+        message: String,
+    },
+    /// Some frames of a batch failed, each with its own error. The others were processed.
+    #[error("{} frame(s) failed:\n  {}", .0.len(), Failures(.0))]
+    Frames(Vec<(String, Error)>),
+}
+
+impl Error {
+    /// The code the CLI exits with for this error. A batch of failed frames exits with the code
+    /// of the first failure.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Config(_) => 2,
+            Error::Io { .. } => 3,
+            Error::Decode { .. } => 4,
+            Error::DimensionMismatch { .. } => 5,
+            Error::CorruptField { .. } => 6,
+            Error::Encode { .. } => 7,
+            Error::Backend { .. } => 8,
+            Error::Frames(failures) => failures.first().map_or(1, |(_, err)| err.exit_code()),
+        }
+    }
+
+    pub fn io(path: &Path, source: io::Error) -> Error {
+        Error::Io {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+
+    pub fn decode(path: &Path, message: impl ToString) -> Error {
+        Error::Decode {
+            path: path.to_path_buf(),
+            message: message.to_string(),
+        }
+    }
+
+    pub fn backend(backend: &'static str, message: impl ToString) -> Error {
+        Error::Backend {
+            backend,
+            message: message.to_string(),
+        }
+    }
+
+    /// Sorts an error of the `image` crate while decoding `path` into I/O or decoding errors.
+    pub fn image(path: &Path, err: image::ImageError) -> Error {
+        match err {
+            image::ImageError::IoError(source) => Error::io(path, source),
+            err => Error::decode(path, err),
+        }
+    }
+
+    /// Sorts an error while reading a binary file written by this program (a checkpoint, say)
+    /// into I/O errors and files that are cut short or malformed.
+    pub fn read(path: &Path, err: io::Error) -> Error {
+        match err.kind() {
+            io::ErrorKind::UnexpectedEof => Error::decode(path, "the file is cut short"),
+            io::ErrorKind::InvalidData => Error::decode(path, err),
+            _ => Error::io(path, err),
+        }
+    }
+}
+
+/// Lists the failures of `Error::Frames`, one per line.
+struct Failures<'a>(&'a [(String, Error)]);
+
+impl fmt::Display for Failures<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, (frame, err)) in self.0.iter().enumerate() {
+            if index > 0 {
+                write!(f, "\n  ")?;
+            }
+            write!(f, "{}: {}", frame, err)?;
+        }
+        Ok(())
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,209 @@
+use std::{
+    cell::RefCell,
+    fmt,
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use image::{codecs::gif::GifDecoder, AnimationDecoder, DynamicImage, ImageReader};
+use tracing::debug_span;
+
+use crate::{
+    error::{Error, Result},
+    procedural::Procedural,
+    shapes,
+};
+
+/// Extensions of the image formats accepted as input, in lower case.
+const EXTENSIONS: [&str; 9] = [
+    "png", "jpg", "jpeg", "bmp", "webp", "tif", "tiff", "gif", "svg",
+];
+
+/// A single input image: an image or SVG file, one frame of an animated GIF, text, or a frame of
+/// a procedural animation.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Frame {
+    File(PathBuf),
+    Gif {
+        path: PathBuf,
+        index: usize,
+    },
+    /// Text drawn with the bundled font.
+    Text(String),
+    /// Frame `index` of a generated animation `count` frames long.
+    Procedural {
+        animation: Procedural,
+        index: usize,
+        count: usize,
+    },
+}
+
+/// The GIF decoder used last on this thread, with the index of the next frame it returns. Frames
+/// are usually loaded in order, and GIF frames can only be decoded one after the other, so this
+/// saves decoding the whole animation again for every frame.
+type GifCursor = (PathBuf, usize, image::Frames<'static>);
+
+thread_local! {
+    static GIF_CURSOR: RefCell<Option<GifCursor>> = const { RefCell::new(None) };
+}
+
+impl Frame {
+    /// Decodes the image of this frame.
+    pub fn load(&self) -> Result<DynamicImage> {
+        let _span = debug_span!("load", frame = %self).entered();
+        match self {
+            Frame::File(path) if is_svg(path) => shapes::render_svg(path),
+            Frame::File(path) => ImageReader::open(path)
+                .and_then(|reader| reader.with_guessed_format())
+                .map_err(|err| Error::io(path, err))?
+                .decode()
+                .map_err(|err| Error::image(path, err)),
+            Frame::Gif { path, index } => GIF_CURSOR.with_borrow_mut(|cursor| {
+                let reusable = cursor
+                    .as_ref()
+                    .is_some_and(|(cursor_path, next, _)| cursor_path == path && next <= index);
+                if !reusable {
+                    *cursor = Some((path.clone(), 0, gif_frames(path)?));
+                }
+
+                let (_, next, frames) = cursor.as_mut().unwrap();
+                let frame = frames.nth(*index - *next);
+                *next = *index + 1;
+                match frame {
+                    Some(Ok(frame)) => Ok(DynamicImage::ImageRgba8(frame.into_buffer())),
+                    Some(Err(err)) => Err(Error::image(path, err)),
+                    None => Err(Error::decode(path, format!("there is no frame {}", index))),
+                }
+            }),
+            Frame::Text(text) => Ok(shapes::render_text(text)),
+            Frame::Procedural {
+                animation,
+                index,
+                count,
+            } => Ok(animation.render(*index, *count)),
+        }
+    }
+
+    /// Where the static field of this frame is cached, if it is cached at all.
+    pub fn field_path(&self) -> Option<PathBuf> {
+        match self {
+            Frame::File(path) => Some(PathBuf::from(format!("{}.field", path.display()))),
+            Frame::Gif { path, index } => Some(PathBuf::from(format!(
+                "{}.{:0>5}.field",
+                path.display(),
+                index
+            ))),
+            // Generating these is quick, there is no need to cache them.
+            Frame::Text(_) | Frame::Procedural { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Frame::File(path) => write!(f, "{}", path.display()),
+            Frame::Gif { path, index } => write!(f, "{} (frame {})", path.display(), index),
+            Frame::Text(text) => write!(f, "text \"{}\"", text),
+            Frame::Procedural {
+                animation, index, ..
+            } => write!(f, "{:?} (frame {})", animation, index),
+        }
+    }
+}
+
+fn gif_frames(path: &Path) -> Result<image::Frames<'static>> {
+    let file = File::open(path).map_err(|err| Error::io(path, err))?;
+    let decoder = GifDecoder::new(BufReader::new(file)).map_err(|err| Error::image(path, err))?;
+    Ok(decoder.into_frames())
+}
+
+/// The extension of `path` in lower case, if it has one.
+fn extension(path: &Path) -> Option<String> {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_lowercase())
+}
+
+/// Whether `path` has the extension of a supported image format.
+fn is_image(path: &Path) -> bool {
+    extension(path).is_some_and(|extension| EXTENSIONS.contains(&extension.as_str()))
+}
+
+fn is_svg(path: &Path) -> bool {
+    extension(path).is_some_and(|extension| extension == "svg")
+}
+
+/// The frames in the image file at `path`: every frame of a GIF, or the image itself otherwise.
+/// Checks that the file can be decoded.
+fn frames_of(path: &Path) -> Result<Vec<Frame>> {
+    if is_svg(path) {
+        shapes::render_svg(path)?;
+        return Ok(vec![Frame::File(path.to_path_buf())]);
+    }
+
+    let reader = ImageReader::open(path)
+        .and_then(|reader| reader.with_guessed_format())
+        .map_err(|err| Error::io(path, err))?;
+    if reader.format() == Some(image::ImageFormat::Gif) {
+        let count = gif_frames(path)?.count();
+        return Ok((0..count)
+            .map(|index| Frame::Gif {
+                path: path.to_path_buf(),
+                index,
+            })
+            .collect());
+    }
+
+    // Reading the header is enough to notice most files that are not images at all.
+    reader
+        .into_dimensions()
+        .map_err(|err| Error::image(path, err))?;
+    Ok(vec![Frame::File(path.to_path_buf())])
+}
+
+/// Lists the frames at `path`: the frames of a single image file, or those of all the image files
+/// in a directory, by alphabetical order. Files without an image extension are skipped. If some
+/// files cannot be decoded, the error (`Error::Frames`) lists all of them.
+pub fn list(path: &Path) -> Result<Vec<Frame>> {
+    let mut files = vec![];
+    if path.is_dir() {
+        let entries = std::fs::read_dir(path).map_err(|err| Error::io(path, err))?;
+        for entry in entries {
+            let entry_path = entry.map_err(|err| Error::io(path, err))?.path();
+            if entry_path.is_file() && is_image(&entry_path) {
+                files.push(entry_path);
+            }
+        }
+        files.sort();
+    } else {
+        files.push(path.to_path_buf());
+    }
+
+    let mut frames = vec![];
+    let mut failures = vec![];
+    for file in files {
+        match frames_of(&file) {
+            Ok(file_frames) => frames.extend(file_frames),
+            Err(err) => failures.push((file.display().to_string(), err)),
+        }
+    }
+
+    if failures.is_empty() {
+        Ok(frames)
+    } else {
+        Err(Error::Frames(failures))
+    }
+}
+
+/// The `count` frames of a procedural animation.
+pub fn procedural(animation: Procedural, count: usize) -> Vec<Frame> {
+    (0..count)
+        .map(|index| Frame::Procedural {
+            animation,
+            index,
+            count,
+        })
+        .collect()
+}
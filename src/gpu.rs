@@ -1,8 +1,9 @@
 use rustacuda::prelude::*;
 use std::error::Error;
 use std::ffi::CString;
+use tracing::{debug, trace};
 
-use crate::gui::{HEIGHT, WIDTH};
+use crate::{HEIGHT, WIDTH};
 
 // WIDTH  = THREADS_X * BLOCKS.X
 // HEIGHT = THREADS_Y * BLOCKS.Y
@@ -17,36 +18,42 @@ const BLOCKS_Y: u32 = (HEIGHT / THREADS_Y) + 1; // 15
 const VALUES: usize = (WIDTH * HEIGHT) as usize;
 
 pub fn cuda_generate_static_field(
-    mass_product: f32,
+    particle_mass: f32,
+    g: f32,
+    falloff_exponent: i32,
     att_x: Vec<i32>,
     att_y: Vec<i32>,
+    att_mass: Vec<f32>,
 ) -> Result<(Vec<f32>, Vec<f32>), Box<dyn Error>> {
     // Set up the context, load the module, and create a stream to run kernels in.
     rustacuda::init(CudaFlags::empty())?;
     let device = Device::get_device(0)?;
-    println!("{}", device.name().unwrap());
+    debug!(device = device.name()?, "Generating field with CUDA");
     let _ctx = Context::create_and_push(ContextFlags::MAP_HOST | ContextFlags::SCHED_AUTO, device)?;
 
     let ptx = CString::new(include_str!("./shaders/static-field.ptx"))?;
     let module = Module::load_from_string(&ptx)?;
     let stream = Stream::new(StreamFlags::DEFAULT, None)?;
 
-    println!("[CUDA] Copying data to device");
+    trace!("Copying data to device");
     // Create buffers for data
     let mut in_x2 = DeviceBuffer::from_slice(att_x.as_slice())?;
     let mut in_y2 = DeviceBuffer::from_slice(att_y.as_slice())?;
+    let mut in_mass2 = DeviceBuffer::from_slice(att_mass.as_slice())?;
     let mut out_y = DeviceBuffer::from_slice(&vec![0.0f32; VALUES])?;
     let mut out_x = DeviceBuffer::from_slice(&vec![0.0f32; VALUES])?;
-    println!("[CUDA] Copying data to device ... DONE");
-    println!("[CUDA] Running kernel for {} attractors", att_x.len());
+    trace!(attractors = att_x.len(), "Running kernel");
 
     // This kernel adds each element in `in_x` and `in_y` and writes the result into `out`.
     unsafe {
-        // gravity(const double mass_product, const int* x2, const int* y2, const int attractors, double* out_x, double* out_y) {
+        // gravity(const float mass1, const float g, const int falloff_exponent, const int* x2, const int* y2, const float* mass2, const int attractors, float* out_x, float* out_y, const int width, const int height) {
         launch!(module.gravity<<<(BLOCKS_X, BLOCKS_Y, 1), (THREADS_X, THREADS_Y, 1), 0, stream>>>(
-            mass_product,
+            particle_mass,
+            g,
+            falloff_exponent,
             in_x2.as_device_ptr(),
             in_y2.as_device_ptr(),
+            in_mass2.as_device_ptr(),
             in_x2.len() as i32,
             out_x.as_device_ptr(),
             out_y.as_device_ptr(),
@@ -58,13 +65,12 @@ pub fn cuda_generate_static_field(
     // Kernel launches are asynchronous, so we wait for the kernels to finish executing.
     stream.synchronize()?;
 
-    println!("[CUDA] Copying data to host");
+    trace!("Copying data to host");
     // Copy the results back to host memory
     let mut out_host_x = vec![0.0f32; VALUES]; //[0.0f32; THREADS];
     let mut out_host_y = vec![0.0f32; VALUES]; //[0.0f32; THREADS];
     out_y.copy_to(&mut out_host_y)?;
     out_x.copy_to(&mut out_host_x)?;
-    println!("[CUDA] Copying data to host ... DONE");
 
     Ok((out_host_x, out_host_y))
 }
@@ -8,57 +8,54 @@ use winit::{
 };
 use winit_input_helper::WinitInputHelper;
 
-pub const WIDTH: u32 = 128;
-pub const HEIGHT: u32 = 128;
+use physics_apple::{Error, Result, HEIGHT, WIDTH};
 
-pub const SCALE: f64 = 2.0;
-
-fn build_window(event_loop: &EventLoop<()>) -> Window {
+fn build_window(event_loop: &EventLoop<()>, scale: f64) -> Result<Window> {
     let size = LogicalSize::new(WIDTH as f64, HEIGHT as f64);
-    let scaled_size = LogicalSize::new(WIDTH as f64 * SCALE, HEIGHT as f64 * SCALE);
+    let scaled_size = LogicalSize::new(WIDTH as f64 * scale, HEIGHT as f64 * scale);
     WindowBuilder::new()
         .with_title("Physics Apple")
         .with_inner_size(scaled_size)
         .with_min_inner_size(size)
         .build(event_loop)
-        .unwrap()
+        .map_err(|err| Error::backend("The window", err))
 }
 
-pub fn run<F1, F2>(mut draw_function: F1, mut update_function: F2)
+/// Shows the frames drawn by `draw_function` in a window until it is closed, calling
+/// `update_function` before each of them. Fails if the window cannot be opened or drawn to, or
+/// as soon as `update_function` fails.
+pub fn run<F1, F2>(scale: f64, mut draw_function: F1, mut update_function: F2) -> Result<()>
 where
     F1: FnMut(&mut [u8]) + 'static,
-    F2: FnMut() + 'static,
+    F2: FnMut() -> Result<()> + 'static,
 {
-    let event_loop = EventLoop::new().expect("Could not create EventLoop");
+    let event_loop = EventLoop::new().map_err(|err| Error::backend("The window", err))?;
     let mut input = WinitInputHelper::new();
 
-    let window = build_window(&event_loop);
+    let window = build_window(&event_loop, scale)?;
     let mut pixels = {
         let window_size = window.inner_size();
         let surface_texture = SurfaceTexture::new(window_size.width, window_size.height, &window);
-        Pixels::new(WIDTH, HEIGHT, surface_texture).unwrap()
+        Pixels::new(WIDTH, HEIGHT, surface_texture)
+            .map_err(|err| Error::backend("The window", err))?
     };
+    let mut failure = None;
 
     event_loop
         .run(|event, ev_window| {
             // The one and only event that winit_input_helper doesn't have for us...
 
-            match &event {
-                Event::WindowEvent {
-                    window_id: _,
-                    event,
-                } => match event {
-                    WindowEvent::RedrawRequested => {
-                        draw_function(pixels.frame_mut());
-                        if let Err(err) = pixels.render() {
-                            println!("[ERROR] pixels.render() failed: {}", err);
-                            ev_window.exit();
-                            return;
-                        }
-                    }
-                    _ => (),
-                },
-                _ => (),
+            if let Event::WindowEvent {
+                event: WindowEvent::RedrawRequested,
+                ..
+            } = &event
+            {
+                draw_function(pixels.frame_mut());
+                if let Err(err) = pixels.render() {
+                    failure = Some(Error::backend("Drawing to the window", err));
+                    ev_window.exit();
+                    return;
+                }
             }
 
             // For everything else, for let winit_input_helper collect events to build its state.
@@ -74,10 +71,16 @@ where
                     return;
                 }
 
-                update_function();
+                if let Err(err) = update_function() {
+                    failure = Some(err);
+                    ev_window.exit();
+                    return;
+                }
 
                 window.request_redraw();
             }
         })
-        .expect("EventLoop failed");
+        .map_err(|err| Error::backend("The window", err))?;
+
+    failure.map_or(Ok(()), Err)
 }
@@ -0,0 +1,47 @@
+//! Physics simulation of particles drawn towards the pixels of an image, frame after frame.
+//!
+//! The usual flow is:
+//! - build a board, either from an image with `physics::generate_board` (which also loads or
+//!   generates its static field) or from a list of attractors with `Board::new` and
+//!   `Board::set_attractors`, followed by `Board::generate_static_field`;
+//! - place particles on it with `Board::spawn_particles`, or build, seed and fill it from a
+//!   configuration in one go with `physics::seeded_board`;
+//! - step it with `Board::update`, or play a whole sequence of frames with
+//!   `schedule::Playback`;
+//! - draw it into a buffer with the functions of `render`, or save every output frame of a
+//!   sequence with `render::render_sequence`.
+//!
+//! The `physics-apple` command line tool is built on top of this library with the `cli` feature,
+//! and its window display with the `gui` feature. Generating fields on the GPU needs the `cuda`
+//! feature.
+
+#[cfg(feature = "cuda")]
+#[macro_use]
+extern crate rustacuda;
+
+pub mod checkpoint;
+pub mod config;
+pub mod error;
+pub mod frames;
+#[cfg(feature = "cuda")]
+mod gpu;
+pub mod physics;
+pub mod procedural;
+pub mod render;
+pub mod schedule;
+pub mod shapes;
+pub mod trajectories;
+
+pub use config::Config;
+pub use error::{Error, Result};
+pub use frames::Frame;
+pub use physics::{
+    board::Board, force::Force, generate_board, seeded_board, Attractor, FieldLoadOutcome,
+};
+
+/// Width of the boards made from text, shapes and procedural frames, and of the fields generated
+/// on the GPU.
+pub const WIDTH: u32 = 128;
+/// Height of the boards made from text, shapes and procedural frames, and of the fields generated
+/// on the GPU.
+pub const HEIGHT: u32 = 128;
@@ -0,0 +1,42 @@
+use std::io::IsTerminal;
+
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::{
+    filter::filter_fn, fmt::format::FmtSpan, layer::SubscriberExt, util::SubscriberInitExt, Layer,
+};
+
+use crate::{cli::LogFormat, profile::ProfileLayer};
+
+/// Sends the messages of the program and the library to the standard error, keeping the standard
+/// output for what commands print on purpose (like the list of presets).
+///
+/// # Arguments
+/// - verbose: How many times `-v` was given. Once shows debug messages and the time every stage
+///   takes (when its span closes), twice shows everything.
+/// - quiet: How many times `-q` was given. Once hides everything below warnings, twice everything
+///   below errors.
+/// - format: Whether to write readable text or JSON lines.
+/// - profile: Where to collect the time of every stage for `--profile-report`. Its spans are
+///   timed whatever the verbosity.
+pub fn init(verbose: u8, quiet: u8, format: LogFormat, profile: Option<ProfileLayer>) {
+    let level = match (verbose, quiet) {
+        (0, 0) => LevelFilter::INFO,
+        (1, _) => LevelFilter::DEBUG,
+        (_, 0) => LevelFilter::TRACE,
+        (_, 1) => LevelFilter::WARN,
+        _ => LevelFilter::ERROR,
+    };
+
+    let layer = tracing_subscriber::fmt::layer()
+        .with_span_events(FmtSpan::CLOSE)
+        .with_target(false)
+        .with_writer(std::io::stderr);
+    let layer = match format {
+        LogFormat::Text => layer.with_ansi(std::io::stderr().is_terminal()).boxed(),
+        LogFormat::Json => layer.json().with_ansi(false).boxed(),
+    };
+    tracing_subscriber::registry()
+        .with(layer.with_filter(level))
+        .with(profile.map(|profile| profile.with_filter(filter_fn(|metadata| metadata.is_span()))))
+        .init();
+}
@@ -1,9 +1,5 @@
-#[macro_use]
-extern crate rustacuda;
-
 use std::{
     cell::RefCell,
-    io::BufWriter,
     path::{Path, PathBuf},
     rc::Rc,
     sync::{
@@ -13,95 +9,280 @@ use std::{
     thread,
 };
 
-use byteorder::{LittleEndian, WriteBytesExt};
-use clap::Parser;
-use cli::{CLIArgs, Commands};
-use gui::{HEIGHT, WIDTH};
-use physics::{force::Force, generate_board, load_image, FieldLoadOutcome};
+use clap::{Parser, ValueEnum};
+use cli::{CLIArgs, Commands, ExportOptions, SimulateOptions};
+use physics_apple::{
+    checkpoint::{self, Checkpoint},
+    config::{AttractorConfig, Config, PhysicsConfig, Preset},
+    frames::{self, Frame},
+    physics::{self, board, force::Force, generate_board, seeded_board, FieldLoadOutcome},
+    render::{self, render_buffer},
+    schedule::Playback,
+    trajectories::{TrajectoryFormat, TrajectoryWriter},
+    Error, Result,
+};
+use profile::Profiler;
+use tracing::{debug_span, error, info, trace, warn};
+use tui::TuiMode;
 
 mod cli;
-mod gpu;
+#[cfg(feature = "gui")]
 mod gui;
-mod physics;
+mod logging;
+mod profile;
+mod tui;
 
-const USE_FPS: bool = true;
-const FPS: u128 = 30;
-
-const USE_FIXED_ITER: bool = false;
-const ITER_PER_FRAME: u32 = 100;
 fn main() {
     let args = CLIArgs::parse();
+    let (profiler, profile_layer) = match args.profile_report {
+        Some(_) => {
+            let (profiler, layer) = Profiler::new();
+            (Some(profiler), Some(layer))
+        }
+        None => (None, None),
+    };
+    logging::init(args.verbose, args.quiet, args.log_format, profile_layer);
 
-    match args.command {
-        Commands::Generate { path, threads, gpu } => {
-            let files = list_directory(path);
-            if files.is_empty() {
-                println!("No files found in directory.");
-                return;
-            }
+    if let Err(err) = check_resume(&args) {
+        exit_with(err);
+    }
 
-            if gpu {
-                generate_fields_gpu(files);
-            } else {
-                generate_fields(
-                    files,
-                    threads.unwrap_or(thread::available_parallelism().unwrap().get()),
-                );
+    let mut overrides = args.overrides.clone();
+    // The shorthand flags are applied last, so that they win over the config file.
+    match &args.command {
+        Commands::SimulateFile {
+            options: SimulateOptions {
+                render_scale, seed, ..
+            },
+            ..
+        }
+        | Commands::SimulateText {
+            options: SimulateOptions {
+                render_scale, seed, ..
+            },
+            ..
+        }
+        | Commands::SimulateSequence {
+            render_scale, seed, ..
+        } => {
+            if let Some(render_scale) = render_scale {
+                overrides.push(format!("render.render_scale={}", render_scale));
+            }
+            if let Some(seed) = seed {
+                overrides.push(format!("seed={}", seed));
             }
         }
-        Commands::ViewField { file } => {
-            view_field(&file);
+        _ => (),
+    }
+    if let Some(extractor) = args.extractor {
+        overrides.push(format!(
+            "attractors.extractor={}",
+            extractor.to_possible_value().unwrap().get_name()
+        ));
+    }
+    if let Some(channel) = args.channel {
+        overrides.push(format!(
+            "attractors.channel={}",
+            channel.to_possible_value().unwrap().get_name()
+        ));
+    }
+
+    let preset = args
+        .preset
+        .as_ref()
+        .map(|name| Preset::find(name, &args.preset_dir));
+    let config = preset
+        .transpose()
+        .and_then(|preset| Config::load(preset.as_ref(), args.config.as_deref(), &overrides));
+    let mut config = match config {
+        Ok(config) => config,
+        Err(err) => exit_with(err),
+    };
+    // Always run with a known seed, so that the effective config can reproduce the run.
+    config
+        .seed
+        .get_or_insert_with(|| rand::random::<u32>() as u64);
+
+    let result = match args.command {
+        Commands::Generate { path, threads, gpu } => {
+            frames::list(Path::new(&path)).and_then(|files| {
+                if files.is_empty() {
+                    warn!("No files found in directory.");
+                    return Ok(());
+                }
+
+                if gpu {
+                    generate_fields_gpu(files, config.physics, config.attractors)
+                } else {
+                    generate_fields(
+                        files,
+                        threads.unwrap_or(thread::available_parallelism().unwrap().get()),
+                        config.physics,
+                        config.attractors,
+                    )
+                }
+            })
+        }
+        Commands::Presets => list_presets(&args.preset_dir),
+        Commands::ViewField {
+            file,
+            attractors,
+            tui,
+        } => first_frame(&file).and_then(|frame| view_field(&frame, &config, attractors, tui)),
+        Commands::SimulateFile { file, options } => {
+            first_frame(&file).and_then(|frame| simulate_image(&frame, &config, &options))
         }
-        Commands::SimulateFile { file } => {
-            simulate_file(&file);
+        Commands::SimulateText { text, options } => {
+            simulate_image(&Frame::Text(text), &config, &options)
         }
-        Commands::SimulateSequence { path, save_to_file } => {
-            let files = list_directory(path);
+        Commands::SimulateSequence {
+            path,
+            procedural,
+            frames: frame_count,
+            save_to_file,
+            checkpoint_every,
+            checkpoint,
+            resume_from,
+            export,
+            tui,
+            ..
+        } => {
+            let files = match (procedural, path) {
+                (Some(animation), _) => Ok(frames::procedural(animation, frame_count as usize)),
+                (None, Some(path)) => frames::list(Path::new(&path)),
+                (None, None) => unreachable!("clap requires a path without --procedural"),
+            };
+            files.and_then(|files| {
+                if files.is_empty() {
+                    warn!("No files found in directory.");
+                    return Ok(());
+                }
 
-            if save_to_file {
-                simulate_and_save_sequence(files)
-            } else {
-                simulate_sequence(files);
-            }
+                if save_to_file {
+                    simulate_and_save_sequence(
+                        files,
+                        &config,
+                        Path::new("./render"),
+                        checkpoint_every,
+                        &checkpoint,
+                        resume_from.as_deref(),
+                        &export,
+                    )
+                } else {
+                    simulate_sequence(files, &config, tui)
+                }
+            })
         }
+    };
+
+    // A failed command is reported too, with the stages that ran until it stopped. Its own error
+    // wins over one of the report.
+    let report = match profiler {
+        Some(profiler) => profiler.report(args.profile_report.flatten().as_deref()),
+        None => Ok(()),
+    };
+    if let Err(err) = result.and(report) {
+        exit_with(err);
     }
 }
 
-fn list_directory(path: String) -> Vec<PathBuf> {
-    let mut files = Vec::new();
-    for entry in std::fs::read_dir(path).expect("Invalid input directory!") {
-        let entry = entry.unwrap();
-        let path = entry.path();
-        if path.extension().unwrap() == "png" {
-            files.push(path);
-        }
+/// A resumed run continues with the configuration saved in its checkpoint, so the options that
+/// change the configuration are refused instead of being ignored.
+fn check_resume(args: &CLIArgs) -> Result<()> {
+    let Commands::SimulateSequence {
+        resume_from: Some(_),
+        render_scale,
+        seed,
+        ..
+    } = &args.command
+    else {
+        return Ok(());
+    };
+    let given: Vec<&str> = [
+        ("--preset", args.preset.is_some()),
+        ("--config", args.config.is_some()),
+        ("--set", !args.overrides.is_empty()),
+        ("--extractor", args.extractor.is_some()),
+        ("--channel", args.channel.is_some()),
+        ("--render-scale", render_scale.is_some()),
+        ("--seed", seed.is_some()),
+    ]
+    .into_iter()
+    .filter_map(|(flag, given)| given.then_some(flag))
+    .collect();
+    if given.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Config(format!(
+            "--resume-from continues with the configuration saved in the checkpoint, it cannot \
+             be combined with {}",
+            given.join(", ")
+        )))
+    }
+}
+
+/// Prints `err` and exits with its exit code.
+fn exit_with(err: Error) -> ! {
+    error!("{}", err);
+    std::process::exit(err.exit_code());
+}
+
+fn list_presets(preset_dir: &Path) -> Result<()> {
+    let presets = Preset::list(preset_dir)?;
+
+    for preset in presets {
+        let origin = match &preset.path {
+            Some(path) => format!(" ({})", path.display()),
+            None => String::new(),
+        };
+        println!("{}{}\n    {}", preset.name, origin, preset.description());
     }
+    Ok(())
+}
 
-    files.sort();
-    files
+/// The frame to use for commands that take a single image: the image itself, or the first frame
+/// of a GIF.
+fn first_frame(file: &str) -> Result<Frame> {
+    let path = Path::new(file);
+    frames::list(path)?
+        .into_iter()
+        .next()
+        .ok_or_else(|| Error::decode(path, "it has no frames"))
 }
 
-fn generate_fields(files: Vec<PathBuf>, thread_count: usize) {
+/// Generates the fields of `files` that are missing or outdated on `thread_count` threads. Frames
+/// that fail are skipped and reported together at the end.
+fn generate_fields(
+    files: Vec<Frame>,
+    thread_count: usize,
+    physics: PhysicsConfig,
+    attractors: AttractorConfig,
+) -> Result<()> {
     let frames = files.len();
     let mut handles = Vec::new();
     let next_frame = Arc::new(AtomicUsize::new(0));
+    let failures = Arc::new(Mutex::new(Vec::new()));
 
     for _ in 0..thread_count {
         let files = files.clone();
         // Clone the arc so each frame gets a unique reference :)
         let next_frame = next_frame.clone();
+        let failures = failures.clone();
         let handle = thread::spawn(move || loop {
             // We don't actually care if this is reordered, though it probably won't.
             let next_idx = next_frame.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
 
             if next_idx < frames {
-                let path_str = files[next_idx].to_str().unwrap();
-                let (board, result) = physics::generate_board(&path_str, false).unwrap();
-
-                if result == FieldLoadOutcome::FieldGenerated {
-                    let str_path = format!("{}.field", path_str);
-                    let path = std::path::Path::new(&str_path);
-                    board.save_field(path).unwrap();
+                let frame = &files[next_idx];
+                let generated = physics::generate_board(frame, false, physics, &attractors)
+                    .and_then(|(board, result)| match (result, frame.field_path()) {
+                        (FieldLoadOutcome::FieldGenerated, Some(path)) => board.save_field(&path),
+                        _ => Ok(()),
+                    });
+
+                if let Err(err) = generated {
+                    error!(%frame, "{}", err);
+                    failures.lock().unwrap().push((frame.to_string(), err));
                 }
             } else {
                 break;
@@ -114,11 +295,25 @@ fn generate_fields(files: Vec<PathBuf>, thread_count: usize) {
     for handle in handles {
         handle.join().unwrap();
     }
+
+    report_failures(
+        frames,
+        Arc::into_inner(failures).unwrap().into_inner().unwrap(),
+    )
 }
 
-fn generate_fields_gpu(files: Vec<PathBuf>) {
-    let result_buffer: Arc<Mutex<Vec<(Vec<Force<f32>>, PathBuf)>>> =
-        Arc::new(Mutex::new(Vec::new()));
+/// A generated field waiting to be written to disk, along with its frame, key and destination.
+type PendingField = (String, Vec<Force<f32>>, u64, PathBuf);
+
+/// Generates the fields of `files` that are missing or outdated on the GPU, while another thread
+/// writes them to disk. Frames that fail are skipped and reported together at the end.
+fn generate_fields_gpu(
+    files: Vec<Frame>,
+    physics: PhysicsConfig,
+    attractors: AttractorConfig,
+) -> Result<()> {
+    let frames = files.len();
+    let result_buffer: Arc<Mutex<Vec<PendingField>>> = Arc::new(Mutex::new(Vec::new()));
 
     let done = Arc::new(AtomicBool::new(false));
 
@@ -126,185 +321,377 @@ fn generate_fields_gpu(files: Vec<PathBuf>) {
         let result_buffer = result_buffer.clone();
         let done = done.clone();
 
-        move || loop {
-            let mut buffer = result_buffer.lock().unwrap();
-            if !buffer.is_empty() {
-                println!("{} fields to write.", buffer.len());
-                let (forces, path) = buffer.pop().unwrap();
-                drop(buffer);
-                let mut file = BufWriter::new(std::fs::File::create(path).unwrap());
-                for force in forces {
-                    file.write_f32::<LittleEndian>(force.x_component).unwrap();
-                    file.write_f32::<LittleEndian>(force.y_component).unwrap();
-                }
-            } else {
-                if done.load(std::sync::atomic::Ordering::SeqCst) {
-                    break;
+        move || {
+            let mut failures = vec![];
+            loop {
+                let mut buffer = result_buffer.lock().unwrap();
+                if !buffer.is_empty() {
+                    trace!(pending = buffer.len(), "Writing field");
+                    let (frame, forces, key, path) = buffer.pop().unwrap();
+                    drop(buffer);
+                    if let Err(err) = board::write_field(&path, key, forces.iter()) {
+                        error!(%frame, "{}", err);
+                        failures.push((frame, err));
+                    }
+                } else {
+                    if done.load(std::sync::atomic::Ordering::SeqCst) {
+                        break;
+                    }
                 }
             }
+            failures
         }
     });
 
-    for file in files {
-        let path_str = file.to_str().unwrap();
-        let (board, result) = physics::generate_board(&path_str, true).unwrap();
-
-        if result == FieldLoadOutcome::FieldGenerated {
-            let str_path = format!("{}.field", path_str);
-            let path_buf = std::path::Path::new(&str_path).to_path_buf();
-            result_buffer
-                .lock()
-                .unwrap()
-                .push((board.to_field(), path_buf));
+    let mut failures = vec![];
+    for frame in files {
+        match physics::generate_board(&frame, true, physics, &attractors) {
+            Ok((board, FieldLoadOutcome::FieldGenerated)) => {
+                if let Some(path) = frame.field_path() {
+                    let key = board.field_key;
+                    result_buffer.lock().unwrap().push((
+                        frame.to_string(),
+                        board.into_field(),
+                        key,
+                        path,
+                    ));
+                }
+            }
+            Ok((_, FieldLoadOutcome::FieldLoaded)) => {}
+            Err(err) => {
+                error!(%frame, "{}", err);
+                failures.push((frame.to_string(), err));
+            }
         }
     }
 
     done.store(true, std::sync::atomic::Ordering::SeqCst);
 
-    file_write_thread.join().unwrap();
+    failures.extend(file_write_thread.join().unwrap());
+    report_failures(frames, failures)
+}
+
+/// Turns the frames that failed during a batch of `frames` frames into a single error, if there
+/// are any.
+fn report_failures(frames: usize, failures: Vec<(String, Error)>) -> Result<()> {
+    info!(
+        "The fields of {} frame(s) are ready.",
+        frames - failures.len()
+    );
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Frames(failures))
+    }
+}
+
+/// Shows the simulation either in a window, or in the terminal if a `TuiMode` is given. Builds
+/// without the `gui` feature always show it in the terminal.
+#[cfg_attr(not(feature = "gui"), allow(unused_variables))]
+fn run_display<F1, F2>(
+    config: &Config,
+    tui: Option<TuiMode>,
+    draw_function: F1,
+    update_function: F2,
+) -> Result<()>
+where
+    F1: FnMut(&mut [u8]) + 'static,
+    F2: FnMut() -> Result<()> + 'static,
+{
+    match tui {
+        Some(mode) => tui::run(mode, draw_function, update_function),
+        #[cfg(feature = "gui")]
+        None => gui::run(config.render.window_scale, draw_function, update_function),
+        #[cfg(not(feature = "gui"))]
+        None => tui::run(TuiMode::default(), draw_function, update_function),
+    }
 }
 
-fn view_field(file: &String) {
-    let board_ref = Rc::new(RefCell::new(generate_board(file, false).unwrap().0));
-    board_ref.borrow_mut().random_particles(WIDTH * HEIGHT / 8);
+fn view_field(
+    frame: &Frame,
+    config: &Config,
+    show_attractors: bool,
+    tui: Option<TuiMode>,
+) -> Result<()> {
+    let board_ref = Rc::new(RefCell::new(
+        generate_board(frame, false, config.physics, &config.attractors)?.0,
+    ));
 
-    gui::run(
+    run_display(
+        config,
+        tui,
         move |buffer| {
-            board_ref.borrow().draw_static_field(buffer);
+            if show_attractors {
+                board_ref.borrow().draw_attractors(buffer);
+            } else {
+                board_ref.borrow().draw_static_field(buffer);
+            }
         },
-        || {},
-    );
+        || Ok(()),
+    )
 }
 
-fn simulate_file(file: &String) {
-    let board_ref = Rc::new(RefCell::new(generate_board(file, false).unwrap().0));
-    board_ref.borrow_mut().random_particles(WIDTH * HEIGHT / 8);
+/// Simulates a single frame, live or headless depending on `options`.
+fn simulate_image(frame: &Frame, config: &Config, options: &SimulateOptions) -> Result<()> {
+    if options.headless {
+        simulate_file_headless(
+            frame,
+            config,
+            options.steps,
+            options.snapshot_every,
+            &options.output,
+        )
+    } else {
+        simulate_file(frame, config, options.tui)
+    }
+}
+
+fn simulate_file(frame: &Frame, config: &Config, tui: Option<TuiMode>) -> Result<()> {
+    let board_ref = Rc::new(RefCell::new(seeded_board(
+        frame,
+        config,
+        config.simulation.pixels_per_particle,
+    )?));
+    let simulation = config.simulation.clone();
+    let render = config.render.clone();
 
     let boar_ref_clone = board_ref.clone();
-    gui::run(
+    run_display(
+        config,
+        tui,
         move |buffer| {
-            board_ref.borrow().draw_particles(buffer);
+            board_ref.borrow().draw_particles(buffer, &render);
         },
         move || {
-            if USE_FPS {
+            let span = debug_span!(
+                "simulate",
+                steps = tracing::field::Empty,
+                particle_updates = tracing::field::Empty
+            )
+            .entered();
+            let particles = boar_ref_clone.borrow().particles.len() as u64;
+            let steps = if simulation.use_fps {
                 let start = std::time::Instant::now();
-                let mut now = start.clone();
-                let mut iter = 0;
-                while (now - start).as_nanos() < (1_000_000_000 / FPS) {
+                let mut now = start;
+                let mut iter: u64 = 0;
+                while (now - start).as_nanos() < (1_000_000_000 / simulation.fps as u128) {
                     boar_ref_clone.borrow_mut().update();
                     now = std::time::Instant::now();
                     iter += 1;
                 }
-                println!("Ran frame for {} iterations.", iter);
-            } else if USE_FIXED_ITER {
-                for _ in 0..ITER_PER_FRAME {
+                trace!(iterations = iter, "Ran frame");
+                iter
+            } else if simulation.use_fixed_iter {
+                for _ in 0..simulation.iter_per_frame {
                     boar_ref_clone.borrow_mut().update();
                 }
+                simulation.iter_per_frame as u64
             } else {
                 boar_ref_clone.borrow_mut().update();
-            }
+                1
+            };
+            span.record("steps", steps);
+            span.record("particle_updates", steps * particles);
+            Ok(())
         },
-    );
+    )
 }
 
-const SEQ_ITER_PER_FRAME: usize = 20;
-// How many frames to output per input frame.
-// On Bad Apple, this will make the video from 30 fps to 60fps
-const FRAME_HOLD: usize = 1;
-
-// How many frames to keep the simulation going after the last input frame has been simulated
-const END_FRAMES: usize = 48 * 5;
-
-const REALTIME_FPS: usize = 30;
-
-fn simulate_sequence(files: Vec<PathBuf>) {
-    let mut file_counter = 0;
-
-    let board_ref = Rc::new(RefCell::new(
-        generate_board(&files[0].to_str().unwrap(), false)
-            .unwrap()
-            .0,
-    ));
-    board_ref.borrow_mut().random_particles(WIDTH * HEIGHT / 16);
-
-    let mut time_since_last_frame = std::time::Instant::now();
+fn simulate_sequence(files: Vec<Frame>, config: &Config, tui: Option<TuiMode>) -> Result<()> {
+    let board_ref = Rc::new(RefCell::new(seeded_board(
+        &files[0],
+        config,
+        config.sequence.pixels_per_particle,
+    )?));
+    let output_fps = config.sequence.output_fps;
+    let render = config.render.clone();
+    let mut playback = Playback::new(files, config);
+
+    // Output frames follow the clock, at most one per update so that a slow machine plays the
+    // sequence slower instead of freezing to catch up.
+    let start = std::time::Instant::now();
+    let mut output_frame = 0;
     let boar_ref_clone = board_ref.clone();
-    gui::run(
+    run_display(
+        config,
+        tui,
         move |buffer| {
-            let elapsed = time_since_last_frame.elapsed();
-            let frame_time = std::time::Duration::from_secs(1) / REALTIME_FPS as u32;
-            if elapsed < frame_time {
-                return;
-            }
-
-            board_ref.borrow().draw_particles(buffer);
-
-            file_counter += 1;
-
-            if file_counter < files.len() {
-                let filename = files[file_counter].to_str().unwrap();
-                physics::update_static_field(
-                    &filename,
-                    &mut board_ref.borrow_mut(),
-                    load_image(&filename),
-                    false,
-                )
-                .unwrap();
-            }
-
-            time_since_last_frame = std::time::Instant::now();
+            board_ref.borrow().draw_particles(buffer, &render);
         },
         move || {
-            for _ in 0..SEQ_ITER_PER_FRAME {
-                boar_ref_clone.borrow_mut().update();
+            if start.elapsed() >= output_fps.time_of(output_frame + 1) {
+                output_frame += 1;
+                playback.advance_to_output(&mut boar_ref_clone.borrow_mut(), output_frame)?;
             }
+            Ok(())
         },
-    );
+    )
 }
 
-fn simulate_and_save_sequence(files: Vec<PathBuf>) {
-    let mut board = generate_board(files[0].to_str().unwrap(), false).unwrap().0;
-    board.random_particles(WIDTH * HEIGHT / 16);
-
-    let mut buffer_array = Box::new([0u8; (WIDTH * HEIGHT * 4) as usize]);
-    let buffer = buffer_array.as_mut_slice();
-
-    let max_frames = files.len() * FRAME_HOLD + END_FRAMES;
-    let frame_count_size = format!("{}", max_frames).len();
-
-    let mut frame_hold_counter = FRAME_HOLD;
-    let mut file_counter = 1;
-
-    for current_frame in 1..=max_frames {
-        // Render to buffer
-        board.draw_particles(buffer);
+/// Simulates the sequence and saves every output frame to `output`, with the configuration.
+///
+/// # Arguments
+/// - files: The input frames.
+/// - config: The configuration, unless the run is resumed.
+/// - output: Directory the frames and the configuration are saved to.
+/// - checkpoint_every: Save a checkpoint to `checkpoint_path` every N output frames.
+/// - resume_from: Continue from this checkpoint, with the configuration saved in it.
+/// - export: Where and how to export the trajectories of the particles, if at all.
+fn simulate_and_save_sequence(
+    files: Vec<Frame>,
+    config: &Config,
+    output: &Path,
+    checkpoint_every: Option<u64>,
+    checkpoint_path: &Path,
+    resume_from: Option<&Path>,
+    export: &ExportOptions,
+) -> Result<()> {
+    let input_frames = files.len() as u64;
+    let (config, mut board, mut buffer, first_frame, position) = match resume_from {
+        Some(path) => {
+            let checkpoint = load_checkpoint(path, input_frames)?;
+            info!(
+                path = %path.display(),
+                frame = checkpoint.output_frame + 1,
+                "Resuming from checkpoint"
+            );
+            (
+                checkpoint.config,
+                checkpoint.board,
+                checkpoint.buffer,
+                checkpoint.output_frame,
+                Some(checkpoint.position),
+            )
+        }
+        None => {
+            let board = seeded_board(&files[0], config, config.sequence.pixels_per_particle)?;
+            let buffer = render_buffer(&board, config);
+            (config.clone(), board, buffer, 0, None)
+        }
+    };
+    let config = &config;
+    config.save(&output.join("config.toml"))?;
 
-        // Save
-        let path_str = format!(
-            "./render/render.{:0>width$}.png",
-            current_frame,
-            width = frame_count_size
-        );
-        let path = Path::new(&path_str);
-        image::save_buffer(path, buffer, WIDTH, HEIGHT, image::ColorType::Rgba8).unwrap();
+    let mut playback = Playback::new(files, config);
+    if let Some((step, input)) = position {
+        playback.resume_at(step, input);
+    }
+    let mut trajectories = export
+        .export_trajectories
+        .as_ref()
+        .map(|path| {
+            let format = export
+                .export_format
+                .unwrap_or_else(|| TrajectoryFormat::from_path(path));
+            TrajectoryWriter::create(
+                path,
+                format,
+                export.export_stride,
+                export.export_sample,
+                &board,
+                config.sequence.output_fps,
+            )
+        })
+        .transpose()?;
+
+    render::render_sequence(
+        &mut playback,
+        &mut board,
+        &mut buffer,
+        config,
+        output,
+        first_frame,
+        |output_frame, board| {
+            if let Some(writer) = &mut trajectories {
+                let time = config.sequence.output_fps.time_of(output_frame);
+                if let Err(err) = writer.record(output_frame, time, board) {
+                    error!("Could not export trajectories: {}", err);
+                    trajectories = None;
+                }
+            }
+        },
+        |output_frame, playback, board, buffer| {
+            if checkpoint_every.is_some_and(|every| output_frame % every == 0) {
+                let saved = checkpoint::save(
+                    checkpoint_path,
+                    config,
+                    input_frames,
+                    output_frame,
+                    playback,
+                    board,
+                    buffer,
+                );
+                if let Err(err) = saved {
+                    error!("Could not save checkpoint: {}", err);
+                }
+            }
+        },
+    )?;
 
-        // Update particles
-        for _ in 0..SEQ_ITER_PER_FRAME {
-            board.update();
-        }
+    if let Some(Err(err)) = trajectories.map(TrajectoryWriter::finish) {
+        error!("Could not export trajectories: {}", err);
+    }
+    Ok(())
+}
 
-        // Update field
-        if frame_hold_counter != 1 {
-            frame_hold_counter -= 1;
-        } else {
-            frame_hold_counter = FRAME_HOLD;
+/// Loads the checkpoint at `path`, which must have been saved for a sequence of `input_frames`
+/// frames.
+fn load_checkpoint(path: &Path, input_frames: u64) -> Result<Checkpoint> {
+    let checkpoint = checkpoint::load(path)?;
+    if checkpoint.input_frames != input_frames {
+        return Err(Error::Config(format!(
+            "'{}' was saved for a sequence of {} frames, not {}",
+            path.display(),
+            checkpoint.input_frames,
+            input_frames
+        )));
+    }
+    Ok(checkpoint)
+}
 
-            if file_counter < files.len() {
-                let filename = files[file_counter].to_str().unwrap();
-                physics::update_static_field(&filename, &mut board, load_image(&filename), false)
-                    .unwrap();
+/// Runs a fixed number of steps without any window, saving snapshots along the way and the final
+/// state as `final.png` in `output`.
+fn simulate_file_headless(
+    frame: &Frame,
+    config: &Config,
+    steps: u32,
+    snapshot_every: Option<u32>,
+    output: &Path,
+) -> Result<()> {
+    let mut board = seeded_board(frame, config, config.simulation.pixels_per_particle)?;
+    let mut buffer = render_buffer(&board, config);
+
+    std::fs::create_dir_all(output).map_err(|err| Error::io(output, err))?;
+    config.save(&output.join("config.toml"))?;
+    let step_count_size = format!("{}", steps).len();
+
+    let mut step = 0;
+    while step < steps {
+        // Updates are timed together up to the next snapshot, one span per update would be
+        // slower than the update itself.
+        let next = snapshot_every
+            .map_or(steps, |every| (step / every + 1) * every)
+            .min(steps);
+        // Particles are only added or removed by a sequence, the count is the same at every update.
+        let particle_updates = (next - step) as u64 * board.particles.len() as u64;
+        debug_span!("simulate", steps = next - step, particle_updates).in_scope(|| {
+            for _ in step..next {
+                board.update();
             }
-            file_counter += 1;
+        });
+        step = next;
+
+        if snapshot_every.is_some_and(|every| step % every == 0) {
+            let file_name = format!("snapshot.{:0>width$}.png", step, width = step_count_size);
+            render::save_render(&board, &mut buffer, config, &output.join(file_name))?;
         }
     }
+
+    render::save_render(&board, &mut buffer, config, &output.join("final.png"))?;
+    info!(
+        "Ran {} steps, saved result to '{}'.",
+        steps,
+        output.join("final.png").display()
+    );
+    Ok(())
 }
@@ -1,11 +1,18 @@
-use std::{error::Error, path::Path};
+use std::collections::HashSet;
 
-use image::GenericImageView;
+use tracing::{debug, debug_span, warn};
 
-use crate::physics::board::{Board, BoardCell};
+use crate::{
+    config::{AttractorConfig, Config, PhysicsConfig},
+    error::{Error, Result},
+    frames::Frame,
+    physics::board::Board,
+};
 
+mod assignment;
 pub mod board;
-mod engine;
+pub mod engine;
+pub mod extract;
 pub mod force;
 pub mod particle;
 
@@ -17,99 +24,292 @@ pub enum FieldLoadOutcome {
     FieldLoaded,
 }
 
-/// Generates a board from an image file. The board will have the same dimensions as the image.
+/// A pixel of the source image that pulls particles towards it, or pushes them away if its mass
+/// is negative.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Attractor {
+    pub x: u32,
+    pub y: u32,
+    pub mass: f32,
+}
+
+/// Generates a board from an input frame. The board will have the same dimensions as the image.
 ///
 /// # Arguments
-/// - file: The path to the image file.
+/// - frame: The input frame, e.g. an image file.
+/// - use_gpu: Generate the static field on the GPU, if it has to be generated.
+/// - physics: The physical constants used by the board.
+/// - attractors: How the pixels of the image are turned into attractors.
 ///
 /// # Returns
 /// A tuple containing the generated board and the outcome of the field loading. The field loading
 /// outcome can be used to determine if the field was generated or loaded from disk.
 pub fn generate_board(
-    file: &str,
+    frame: &Frame,
     use_gpu: bool,
-) -> Result<(Board, FieldLoadOutcome), Box<dyn Error>> {
-    println!("[Debug] Generating board for '{}'.", file);
+    physics: PhysicsConfig,
+    attractors: &AttractorConfig,
+) -> Result<(Board, FieldLoadOutcome)> {
+    debug!(%frame, "Generating board");
 
     // Load Image
-    let img = load_image(file);
-
-    // Create Board
-    let mut cells = Vec::with_capacity((img.width() * img.height()) as usize);
-    for y in 0..img.height() {
-        for x in 0..img.width() {
-            cells.push(BoardCell::new(x, y));
-        }
-    }
+    let img = frame.load()?;
 
-    let particles = vec![];
-    let mut board = Board {
-        width: img.width(),
-        heigth: img.height(),
-        cells: cells,
-        particles: particles,
-    };
+    let mut board = Board::new(img.width(), img.height(), physics);
 
     // Try loading or generating the static field.
-    let field_result = update_static_field(file, &mut board, img, use_gpu)?;
+    let field_result = update_static_field(frame, &mut board, img, attractors, use_gpu)?;
 
     Ok((board, field_result))
 }
 
-pub fn load_image(full_path: &str) -> image::DynamicImage {
-    let path = Path::new(&full_path);
-    image::open(path).unwrap().grayscale()
+/// Generates the board of `frame` (on the CPU) as `generate_board` does, seeds it with the
+/// configured seed, if any, and places one particle per `pixels_per_particle` pixels of the frame
+/// on it, as the configuration says.
+pub fn seeded_board(frame: &Frame, config: &Config, pixels_per_particle: u32) -> Result<Board> {
+    let mut board = generate_board(frame, false, config.physics, &config.attractors)?.0;
+    if let Some(seed) = config.seed {
+        board.seed(seed);
+    }
+    board.spawn_particles(
+        board.width * board.heigth / pixels_per_particle,
+        &config.spawn,
+    );
+    Ok(board)
 }
 
+/// Switches `board` to the attractors of `img`, the image of `frame`, and loads the static field
+/// cached for it, or generates it if there is none or it is outdated.
+///
+/// # Arguments
+/// - frame: The input frame `img` was loaded from.
+/// - board: The board to update, which must have the size of `img`.
+/// - img: The image of the frame.
+/// - attractor_config: How the pixels of the image are turned into attractors.
+/// - use_gpu: Generate the static field on the GPU, if it has to be generated.
+///
+/// # Returns
+/// Whether the field was loaded or generated. Fails if `img` has another size than the board, or
+/// if the field has to be generated and cannot be. A cached field that is corrupt or unreadable
+/// is generated again.
 pub fn update_static_field(
-    frame_filename: &str,
+    frame: &Frame,
     board: &mut Board,
     img: image::DynamicImage,
+    attractor_config: &AttractorConfig,
     use_gpu: bool,
-) -> Result<FieldLoadOutcome, Box<dyn Error>> {
-    let str_field_path = format!("{}.field", frame_filename);
-    let field_path = Path::new(&str_field_path);
-    if Path::exists(&field_path) {
-        println!(
-            "[Debug] Found static attraction field for '{}'.",
-            frame_filename
-        );
-
-        let load_result = board.load_static_field(field_path);
-        if load_result.is_ok() {
-            if !board.is_field_corrupted() {
-                return Ok(FieldLoadOutcome::FieldLoaded);
-            } else {
-                board.clear_static_field();
+) -> Result<FieldLoadOutcome> {
+    let _span = debug_span!("field", %frame).entered();
+    if (img.width(), img.height()) != (board.width, board.heigth) {
+        return Err(Error::DimensionMismatch {
+            frame: frame.to_string(),
+            expected: (board.width, board.heigth),
+            actual: (img.width(), img.height()),
+        });
+    }
+
+    board.set_attractors(get_attractors(
+        &img,
+        attractor_config,
+        board.physics.attractor_mass,
+    ));
+
+    if let Some(field_path) = frame.field_path().filter(|path| path.exists()) {
+        debug!(path = %field_path.display(), "Found static attraction field");
+
+        // A cache that cannot be used is only a warning, the field is generated again.
+        match board.load_static_field(&field_path, board.field_key) {
+            Ok(true) => return Ok(FieldLoadOutcome::FieldLoaded),
+            Ok(false) => {
+                debug!(path = %field_path.display(), "Outdated field, it was generated with other parameters")
             }
+            Err(err) => warn!("{}, generating it again", err),
         }
-        println!("Corrupted field '{frame_filename}'")
     }
 
-    println!(
-        "[Debug] Generating static attraction field for '{}'.",
-        frame_filename
+    debug!(
+        attractors = board.attractors.len(),
+        gpu = use_gpu,
+        "Generating static attraction field"
     );
 
     if use_gpu {
-        board.cuda_generate_static_field(get_attractors(img));
+        #[cfg(feature = "cuda")]
+        board.cuda_generate_static_field()?;
+        #[cfg(not(feature = "cuda"))]
+        return Err(Error::backend(
+            "CUDA",
+            "this build has no GPU support (the `cuda` feature)",
+        ));
     } else {
-        board.generate_static_field(get_attractors(img));
+        board.generate_static_field();
     }
     Ok(FieldLoadOutcome::FieldGenerated)
 }
 
-fn get_attractors(img: image::DynamicImage) -> Vec<(u32, u32)> {
+/// Hashes everything the static field of `board` depends on: its size, the physical constants
+/// and the attractors. Uses 64-bit FNV-1a, which (unlike `DefaultHasher`) is guaranteed to stay
+/// the same between Rust versions, so keys stored on disk remain valid.
+fn field_key(board: &Board) -> u64 {
+    let physics = &board.physics;
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let mut write = |bytes: &[u8]| {
+        for byte in bytes {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    };
+
+    write(&board.width.to_le_bytes());
+    write(&board.heigth.to_le_bytes());
+    write(&physics.g.to_le_bytes());
+    write(&physics.particle_mass.to_le_bytes());
+    write(&physics.force_law.exponent().to_le_bytes());
+    for attractor in &board.attractors {
+        write(&attractor.x.to_le_bytes());
+        write(&attractor.y.to_le_bytes());
+        write(&attractor.mass.to_le_bytes());
+    }
+
+    hash
+}
+
+/// Turns the pixels of an image into attractors, with a mass depending on their brightness after
+/// extraction (see `extract::extract`) as set by `config`. A pixel of full weight gets
+/// `attractor_mass`, repelling dark pixels get a negative mass.
+pub fn get_attractors(
+    img: &image::DynamicImage,
+    config: &AttractorConfig,
+    attractor_mass: f32,
+) -> Vec<Attractor> {
     let mut attractors = vec![];
-    for (x, y, color) in img.pixels() {
-        if filter_white(color) {
-            attractors.push((x, y));
+    for (x, y, value) in extract::extract(img, config).enumerate_pixels() {
+        if let Some(weight) = config.weight(value.0[0]) {
+            attractors.push(Attractor {
+                x,
+                y,
+                mass: attractor_mass * weight,
+            });
         }
     }
-    return attractors;
+    attractors
+}
+
+/// How much two sets of attractors overlap, from 0 (not at all) to 1 (same pixels): the number of
+/// pixels attracting in both, over the number attracting in either. Repelling pixels are left
+/// out, as is the mass. Two empty sets overlap fully.
+pub fn mask_overlap(a: &[Attractor], b: &[Attractor]) -> f32 {
+    let mask = |attractors: &[Attractor]| -> HashSet<(u32, u32)> {
+        attractors
+            .iter()
+            .filter(|attractor| attractor.mass > 0.0)
+            .map(|attractor| (attractor.x, attractor.y))
+            .collect()
+    };
+    let (a, b) = (mask(a), mask(b));
+
+    let union = a.union(&b).count();
+    if union == 0 {
+        return 1.0;
+    }
+    a.intersection(&b).count() as f32 / union as f32
 }
 
-/// Returns true if the pixel is "close enough" to white. In this case, "close enough" means all color channels are above half active.
-fn filter_white(color: image::Rgba<u8>) -> bool {
-    color.0[0] > 255 / 2 && color.0[1] > 255 / 2 && color.0[2] > 255 / 2 && color.0[3] > 255 / 2
+#[cfg(test)]
+mod tests {
+    use image::{GenericImageView, GrayImage, Luma, Rgba, RgbaImage};
+
+    use super::*;
+
+    /// The test images used to go through before they became attractors: turned to grayscale
+    /// when loaded, then kept if all channels, transparency included, were above half.
+    fn old_filter_white(img: &image::DynamicImage) -> Vec<(u32, u32)> {
+        let filter_white = |color: Rgba<u8>| {
+            color.0[0] > 255 / 2
+                && color.0[1] > 255 / 2
+                && color.0[2] > 255 / 2
+                && color.0[3] > 255 / 2
+        };
+        img.grayscale()
+            .pixels()
+            .filter(|&(_, _, color)| filter_white(color))
+            .map(|(x, y, _)| (x, y))
+            .collect()
+    }
+
+    #[test]
+    fn default_attractors_match_the_old_white_filter() {
+        // Primaries and their mixes, grays around the middle, then a spread of other colours,
+        // each at several opacities.
+        let mut colors = vec![
+            [255, 0, 0],
+            [0, 255, 0],
+            [0, 0, 255],
+            [255, 255, 0],
+            [0, 255, 255],
+            [255, 0, 255],
+            [255, 255, 255],
+            [126, 126, 126],
+            [127, 127, 127],
+            [128, 128, 128],
+            [129, 129, 129],
+        ];
+        colors.extend((0..500u32).map(|index| {
+            let hash = index.wrapping_mul(2654435761);
+            [hash as u8, (hash >> 8) as u8, (hash >> 16) as u8]
+        }));
+        let alphas = [0, 127, 128, 200, 255];
+        let img = RgbaImage::from_fn(colors.len() as u32, alphas.len() as u32, |x, y| {
+            let [r, g, b] = colors[x as usize];
+            Rgba([r, g, b, alphas[y as usize]])
+        });
+        let img = image::DynamicImage::ImageRgba8(img);
+
+        let attractors = get_attractors(&img, &AttractorConfig::default(), 10.0);
+        let positions: Vec<(u32, u32)> = attractors
+            .iter()
+            .map(|attractor| (attractor.x, attractor.y))
+            .collect();
+        assert_eq!(positions, old_filter_white(&img));
+        assert!(attractors.iter().all(|attractor| attractor.mass == 10.0));
+        // Pure red is too dark, yellow bright enough, as before.
+        assert!(!positions.contains(&(0, 4)));
+        assert!(positions.contains(&(3, 4)));
+    }
+
+    #[test]
+    fn corrupt_cached_fields_are_generated_again() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("square.png");
+        GrayImage::from_fn(16, 16, |x, y| {
+            Luma([if (4..12).contains(&x) && (4..12).contains(&y) {
+                255
+            } else {
+                0
+            }])
+        })
+        .save(&path)
+        .unwrap();
+        let frame = Frame::File(path);
+        let config = Config::default();
+
+        let (board, outcome) =
+            generate_board(&frame, false, config.physics, &config.attractors).unwrap();
+        assert!(outcome == FieldLoadOutcome::FieldGenerated);
+        let field_path = frame.field_path().unwrap();
+        board.save_field(&field_path).unwrap();
+        let (_, outcome) =
+            generate_board(&frame, false, config.physics, &config.attractors).unwrap();
+        assert!(outcome == FieldLoadOutcome::FieldLoaded);
+
+        let bytes = std::fs::read(&field_path).unwrap();
+        std::fs::write(&field_path, &bytes[..bytes.len() / 2]).unwrap();
+        let (regenerated, outcome) =
+            generate_board(&frame, false, config.physics, &config.attractors).unwrap();
+        assert!(outcome == FieldLoadOutcome::FieldGenerated);
+        for (regenerated, generated) in regenerated.field().iter().zip(board.field()) {
+            assert_eq!(regenerated.x_component, generated.x_component);
+            assert_eq!(regenerated.y_component, generated.y_component);
+        }
+    }
 }
@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+
+use rand::{seq::SliceRandom, Rng};
+use rand_chacha::ChaCha8Rng;
+
+/// Matches every particle to a target pixel, so that each pixel gets about as many particles as
+/// the others and the total squared distance the particles travel is small. This approximates
+/// the optimal transport between particles and targets: a rough first matching is improved by
+/// swapping the targets of random pairs of particles whenever that shortens their paths.
+///
+/// Particles whose previous target is still among `targets` keep it, so between two similar
+/// frames only the particles of pixels that changed need a new target.
+///
+/// # Arguments
+/// - positions: Where each particle is.
+/// - previous: The target each particle had so far, if any.
+/// - targets: The pixels to cover, each listed once.
+/// - passes: How many rounds of swaps to try, each as many as there are particles to assign.
+/// - rng: Source of randomness, for repeatable runs.
+///
+/// # Returns
+/// The target of each particle, in the same order as `positions`. `None` for all of them if
+/// there are no targets.
+pub fn assign(
+    positions: &[(f32, f32)],
+    previous: &[Option<(u32, u32)>],
+    targets: &[(u32, u32)],
+    passes: u32,
+    rng: &mut ChaCha8Rng,
+) -> Vec<Option<(u32, u32)>> {
+    let count = positions.len();
+    if targets.is_empty() || count == 0 {
+        return vec![None; count];
+    }
+
+    // With fewer targets than particles, targets are shared as evenly as possible.
+    let capacity = count.div_ceil(targets.len());
+    let mut room: HashMap<(u32, u32), usize> =
+        targets.iter().map(|&target| (target, capacity)).collect();
+
+    let mut assigned: Vec<Option<(u32, u32)>> = vec![None; count];
+    for (particle, target) in previous.iter().enumerate() {
+        if let Some(target) = target {
+            if let Some(left) = room.get_mut(target).filter(|left| **left > 0) {
+                *left -= 1;
+                assigned[particle] = Some(*target);
+            }
+        }
+    }
+
+    // Shuffled, so that when there are more targets than particles the ones left out are spread
+    // over the whole image.
+    let mut free: Vec<(u32, u32)> = targets
+        .iter()
+        .flat_map(|target| std::iter::repeat_n(*target, room[target]))
+        .collect();
+    free.shuffle(rng);
+
+    let mut unassigned: Vec<usize> = (0..count)
+        .filter(|&particle| assigned[particle].is_none())
+        .collect();
+    free.truncate(unassigned.len());
+
+    // Pairing both in Z-order keeps most particles close to their first target.
+    let position_order = |&(x, y): &(f32, f32)| z_order(x.max(0.0) as u32, y.max(0.0) as u32);
+    unassigned.sort_by_key(|&particle| position_order(&positions[particle]));
+    free.sort_by_key(|&(x, y)| z_order(x, y));
+    for (&particle, target) in unassigned.iter().zip(free) {
+        assigned[particle] = Some(target);
+    }
+
+    let mut assigned: Vec<(u32, u32)> = assigned.into_iter().map(Option::unwrap).collect();
+    let cost = |particle: usize, target: (u32, u32)| {
+        let (x, y) = positions[particle];
+        (x - target.0 as f32).powi(2) + (y - target.1 as f32).powi(2)
+    };
+    // Only the particles that got a new target swap, so the others keep theirs.
+    let moving = unassigned.len();
+    for _ in 0..passes as usize * moving {
+        let (a, b) = (
+            unassigned[rng.random_range(0..moving)],
+            unassigned[rng.random_range(0..moving)],
+        );
+        let current = cost(a, assigned[a]) + cost(b, assigned[b]);
+        let swapped = cost(a, assigned[b]) + cost(b, assigned[a]);
+        if swapped < current {
+            assigned.swap(a, b);
+        }
+    }
+
+    assigned.into_iter().map(Some).collect()
+}
+
+/// Position of a pixel along the Z-order curve, which keeps pixels that are close on the board
+/// mostly close in the order.
+fn z_order(x: u32, y: u32) -> u64 {
+    let spread = |value: u32| {
+        let mut value = value as u64 & 0xffff_ffff;
+        value = (value | (value << 16)) & 0x0000_ffff_0000_ffff;
+        value = (value | (value << 8)) & 0x00ff_00ff_00ff_00ff;
+        value = (value | (value << 4)) & 0x0f0f_0f0f_0f0f_0f0f;
+        value = (value | (value << 2)) & 0x3333_3333_3333_3333;
+        (value | (value << 1)) & 0x5555_5555_5555_5555
+    };
+    spread(x) | (spread(y) << 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+
+    fn grid(width: u32, height: u32) -> Vec<(u32, u32)> {
+        (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .collect()
+    }
+
+    fn random_positions(count: usize, rng: &mut ChaCha8Rng) -> Vec<(f32, f32)> {
+        (0..count)
+            .map(|_| (rng.random_range(0.0..32.0), rng.random_range(0.0..32.0)))
+            .collect()
+    }
+
+    fn counts(assigned: &[Option<(u32, u32)>]) -> HashMap<(u32, u32), usize> {
+        let mut counts = HashMap::new();
+        for target in assigned {
+            *counts.entry(target.unwrap()).or_default() += 1;
+        }
+        counts
+    }
+
+    #[test]
+    fn every_particle_gets_a_target() {
+        let mut rng = ChaCha8Rng::seed_from_u64(3);
+        let targets = grid(8, 8);
+        for count in [1, 10, 64, 100, 300] {
+            let positions = random_positions(count, &mut rng);
+            let assigned = assign(&positions, &vec![None; count], &targets, 4, &mut rng);
+            assert_eq!(assigned.len(), count);
+            assert!(assigned
+                .iter()
+                .all(|target| target.is_some_and(|target| targets.contains(&target))));
+        }
+        assert_eq!(assign(&[(1.0, 1.0)], &[None], &[], 4, &mut rng), [None]);
+    }
+
+    #[test]
+    fn targets_are_shared_evenly() {
+        let mut rng = ChaCha8Rng::seed_from_u64(4);
+        let targets = grid(4, 5);
+        for count in [20, 21, 45, 100] {
+            let positions = random_positions(count, &mut rng);
+            // Some previous targets pile up on one pixel, more than it can take.
+            let previous: Vec<Option<(u32, u32)>> = (0..count)
+                .map(|particle| (particle % 2 == 0).then_some((0, 0)))
+                .collect();
+            let assigned = assign(&positions, &previous, &targets, 4, &mut rng);
+            let capacity = count.div_ceil(targets.len());
+            assert!(counts(&assigned).values().all(|&taken| taken <= capacity));
+        }
+    }
+
+    #[test]
+    fn surviving_targets_are_kept() {
+        let mut rng = ChaCha8Rng::seed_from_u64(5);
+        let before = grid(6, 6);
+        let positions = random_positions(36, &mut rng);
+        let previous = assign(&positions, &vec![None; 36], &before, 4, &mut rng);
+
+        // Half of the pixels go away and as many new ones appear.
+        let after: Vec<(u32, u32)> = before
+            .iter()
+            .map(|&(x, y)| if x < 3 { (x, y) } else { (x + 10, y) })
+            .collect();
+        let assigned = assign(&positions, &previous, &after, 4, &mut rng);
+        for (previous, assigned) in previous.iter().zip(&assigned) {
+            if previous.is_some_and(|target| after.contains(&target)) {
+                assert_eq!(assigned, previous);
+            } else {
+                assert!(assigned.is_some_and(|target| target.0 >= 13));
+            }
+        }
+        assert!(counts(&assigned).values().all(|&taken| taken == 1));
+    }
+}
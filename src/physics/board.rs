@@ -1,25 +1,66 @@
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use std::{
     cell::RefCell,
+    f32::consts::TAU,
     fs::File,
-    io::{Cursor, Error, Read},
+    io::{self, BufWriter, Cursor, Read, Write},
+    path::Path,
     rc::Rc,
 };
 
-use rand::Rng;
+use rand::{seq::IndexedRandom, Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use tracing::warn;
 
-use super::{engine::gravitational_force, force::Force, particle::Particle};
+use super::{assignment, engine::gravitational_force, force::Force, particle::Particle, Attractor};
+#[cfg(feature = "cuda")]
+use crate::gpu;
 use crate::{
-    gpu,
-    physics::engine::{ATTRACTOR_MASS, PARTICLE_MASS},
+    config::{
+        CutConfig, CutPolicy, Interpolation, PhysicsConfig, PhysicsMode, PopulationConfig,
+        RenderConfig, RenderStyle, SpawnConfig, SpawnMode,
+    },
+    error::{Error, Result},
 };
+
+mod state;
+
 pub struct Board {
     pub width: u32,
     pub heigth: u32,
     pub cells: Vec<BoardCell>,
     pub particles: Vec<Rc<RefCell<Particle>>>,
+    /// Source of randomness for everything happening on the board, so that seeded runs can be
+    /// repeated exactly.
+    pub rng: ChaCha8Rng,
+    pub physics: PhysicsConfig,
+    /// The attractors of the current frame, which the static field is made of.
+    pub attractors: Vec<Attractor>,
+    /// Identifies the parameters and attractors the static field was generated from. Stored in
+    /// `.field` files, to notice when they are outdated.
+    pub field_key: u64,
+    /// The transition to the current static field that is in progress, if any.
+    pub field_blend: Option<FieldBlend>,
+    /// Whether the attractors or particles changed since particles were last matched to targets,
+    /// in `assignment` mode.
+    pub targets_outdated: bool,
+    /// The id the next particle added to the board gets.
+    pub next_particle_id: u64,
+}
+
+/// A transition from the static field of the previous frame to the current one, spread over a
+/// number of updates. See `Board::start_field_blend`.
+pub struct FieldBlend {
+    from: Vec<Force<f32>>,
+    to: Vec<Force<f32>>,
+    interpolation: Interpolation,
+    step: u32,
+    steps: u32,
 }
 
+/// Start of every `.field` file, followed by the field key and the forces.
+const FIELD_MAGIC: &[u8; 8] = b"PAFIELD1";
+
 #[derive(Default)]
 pub struct BoardCell {
     pub x: u32,
@@ -30,22 +71,61 @@ pub struct BoardCell {
 impl BoardCell {
     pub fn new(x: u32, y: u32) -> BoardCell {
         BoardCell {
-            x: x,
-            y: y,
+            x,
+            y,
             ..Default::default()
         }
     }
 }
 
 impl Board {
-    pub fn generate_static_field(&mut self, attractors: Vec<(u32, u32)>) {
-        for y in 0..self.heigth {
-            // println!("[DEBUG] y = {}", y);
-            for x in 0..self.width {
-                for (a_x, a_y) in &attractors {
-                    self.get_cell_mut(x, y).static_field +=
-                        gravitational_force(x, y, PARTICLE_MASS, *a_x, *a_y, ATTRACTOR_MASS);
-                }
+    /// Creates an empty board, without attractors, particles or static field.
+    pub fn new(width: u32, heigth: u32, physics: PhysicsConfig) -> Board {
+        let mut cells = Vec::with_capacity((width * heigth) as usize);
+        for y in 0..heigth {
+            for x in 0..width {
+                cells.push(BoardCell::new(x, y));
+            }
+        }
+
+        Board {
+            width,
+            heigth,
+            cells,
+            particles: vec![],
+            rng: ChaCha8Rng::from_os_rng(),
+            physics,
+            attractors: vec![],
+            field_key: 0,
+            field_blend: None,
+            targets_outdated: true,
+            next_particle_id: 0,
+        }
+    }
+
+    /// Replaces the attractors of the board. The static field is left as it is, generate or
+    /// load the one of the new attractors afterwards.
+    pub fn set_attractors(&mut self, attractors: Vec<Attractor>) {
+        self.attractors = attractors;
+        self.field_key = super::field_key(self);
+        self.targets_outdated = true;
+    }
+
+    /// Computes the static field from `self.attractors`.
+    pub fn generate_static_field(&mut self) {
+        self.clear_static_field();
+        let physics = self.physics;
+        for cell in self.cells.iter_mut() {
+            for attractor in &self.attractors {
+                cell.static_field += gravitational_force(
+                    cell.x,
+                    cell.y,
+                    physics.particle_mass,
+                    attractor.x,
+                    attractor.y,
+                    attractor.mass,
+                    &physics,
+                );
             }
         }
     }
@@ -57,26 +137,47 @@ impl Board {
             }
         }
     }
-    pub fn cuda_generate_static_field(&mut self, attractors: Vec<(u32, u32)>) {
+    /// Computes the static field from `self.attractors` on the GPU. The kernel only handles
+    /// boards of `WIDTH` by `HEIGHT` pixels.
+    #[cfg(feature = "cuda")]
+    pub fn cuda_generate_static_field(&mut self) -> Result<()> {
+        if (self.width, self.heigth) != (crate::WIDTH, crate::HEIGHT) {
+            return Err(Error::backend(
+                "CUDA",
+                format!(
+                    "the kernel only handles {}x{} boards, not {}x{}",
+                    crate::WIDTH,
+                    crate::HEIGHT,
+                    self.width,
+                    self.heigth
+                ),
+            ));
+        }
+
         let mut attr_x = vec![];
         let mut attr_y = vec![];
-        for (a_x, a_y) in &attractors {
-            attr_x.push(*a_x as i32);
-            attr_y.push(*a_y as i32);
+        let mut attr_mass = vec![];
+        for attractor in &self.attractors {
+            attr_x.push(attractor.x as i32);
+            attr_y.push(attractor.y as i32);
+            attr_mass.push(attractor.mass);
         }
 
-        let (force_x, force_y) =
-            gpu::cuda_generate_static_field(PARTICLE_MASS * ATTRACTOR_MASS, attr_x, attr_y)
-                .unwrap();
+        let (force_x, force_y) = gpu::cuda_generate_static_field(
+            self.physics.particle_mass,
+            self.physics.g,
+            self.physics.force_law.exponent(),
+            attr_x,
+            attr_y,
+            attr_mass,
+        )
+        .map_err(|err| Error::backend("CUDA", err))?;
 
-        println!("[DEBUG] GPU PROCESSING DONE");
+        tracing::trace!("GPU processing done");
 
         for y in 0..self.heigth {
             for x in 0..self.width {
                 let index = (x + y * self.width) as usize;
-                if force_y[index] < 0.0 {
-                    // println!("Y Force at copy can be negative");
-                }
                 self.get_cell_mut(x, y).static_field = Force {
                     x_component: force_x[index],
                     y_component: force_y[index],
@@ -84,10 +185,16 @@ impl Board {
             }
         }
 
-        println!("[DEBUG] Data copied");
+        tracing::trace!("Field copied from the GPU");
+        Ok(())
     }
 
     pub fn update(&mut self) {
+        self.advance_field_blend();
+        if self.physics.mode == PhysicsMode::Assignment && self.targets_outdated {
+            self.assign_targets();
+        }
+
         // Update velocities of particles
         for particle_ref in &self.particles {
             let mut particle = particle_ref.borrow_mut();
@@ -98,15 +205,21 @@ impl Board {
                 particle.velocity = Force::default();
             }
             let (x, y) = particle.get_render_position(self.width - 1, self.heigth - 1);
-            let force = self.get_cell(x, y).static_field.clone(); // + attraction to other particles, in future
-            particle.update_velocity(force);
+            let field = self.get_cell(x, y).static_field.clone(); // + attraction to other particles, in future
+            let force = match self.physics.mode {
+                PhysicsMode::Gravity => field,
+                PhysicsMode::Assignment => {
+                    field * self.physics.field_weight + particle.spring_force(&self.physics)
+                }
+            };
+            particle.update_velocity(force, &self.physics);
         }
         // Update positions of particles
         // TODO: cloning the whole particle array seems expensive... should fix later (do measure first)
         for particle_ref in self.particles.clone() {
             let mut particle = particle_ref.borrow_mut();
             let (x, y) = particle.get_render_position(self.width - 1, self.heigth - 1);
-            particle.update_position();
+            particle.update_position(&self.physics);
 
             // Move particle to new cell, if needed
             let (new_x, new_y) = particle.get_render_position(self.width - 1, self.heigth - 1);
@@ -119,34 +232,375 @@ impl Board {
         }
     }
 
+    /// Makes everything random on the board (e.g. particle placement) repeatable.
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = ChaCha8Rng::seed_from_u64(seed);
+    }
+
+    /// Places `amount` particles on the board, as described by `spawn`.
+    pub fn spawn_particles(&mut self, amount: u32, spawn: &SpawnConfig) {
+        self.targets_outdated = true;
+        match spawn.mode {
+            SpawnMode::Random => self.random_particles(amount),
+            SpawnMode::Grid => self.grid_particles(amount),
+            SpawnMode::Orbit => self.orbiting_particles(amount, spawn.orbit_speed),
+        }
+    }
+
     pub fn random_particles(&mut self, amount: u32) {
         for _ in 0..amount {
-            let x = rand::rng().random_range(0..self.width);
-            let y = rand::rng().random_range(0..self.heigth);
+            let x = self.rng.random_range(0..self.width);
+            let y = self.rng.random_range(0..self.heigth);
 
             self.add_particle(x, y);
         }
     }
 
+    /// Places `amount` particles on an evenly spaced grid, as close to square cells as possible.
+    pub fn grid_particles(&mut self, amount: u32) {
+        if amount == 0 {
+            return;
+        }
+
+        let columns = ((amount as f32 * self.width as f32 / self.heigth as f32)
+            .sqrt()
+            .round() as u32)
+            .clamp(1, amount);
+        let rows = amount.div_ceil(columns);
+        let spacing_x = self.width as f32 / columns as f32;
+        let spacing_y = self.heigth as f32 / rows as f32;
+
+        for index in 0..amount {
+            let x = ((index % columns) as f32 + 0.5) * spacing_x;
+            let y = ((index / columns) as f32 + 0.5) * spacing_y;
+            self.insert_particle(Particle {
+                x: x.min((self.width - 1) as f32),
+                y: y.min((self.heigth - 1) as f32),
+                ..Default::default()
+            });
+        }
+    }
+
+    /// Places `amount` particles randomly, each moving counter-clockwise around the center of the
+    /// board at `speed`.
+    pub fn orbiting_particles(&mut self, amount: u32, speed: f32) {
+        let center_x = (self.width - 1) as f32 / 2.0;
+        let center_y = (self.heigth - 1) as f32 / 2.0;
+
+        for _ in 0..amount {
+            let x = self.rng.random_range(0..self.width) as f32;
+            let y = self.rng.random_range(0..self.heigth) as f32;
+
+            let (dx, dy) = (x - center_x, y - center_y);
+            let distance = (dx * dx + dy * dy).sqrt();
+            let velocity = if distance == 0.0 {
+                Force::default()
+            } else {
+                Force {
+                    x_component: dy / distance * speed,
+                    y_component: -dx / distance * speed,
+                }
+            };
+
+            self.insert_particle(Particle {
+                x,
+                y,
+                velocity,
+                ..Default::default()
+            });
+        }
+    }
+
     pub fn add_particle(&mut self, x: u32, y: u32) {
-        let particle = Particle {
+        self.insert_particle(Particle {
             x: x as f32,
             y: y as f32,
-            velocity: Force::default(),
-        };
+            ..Default::default()
+        });
+    }
+
+    /// Adds `particle` to the board, with the next free id.
+    fn insert_particle(&mut self, mut particle: Particle) {
+        particle.id = self.next_particle_id;
+        self.next_particle_id += 1;
 
+        let (x, y) = particle.get_render_position(self.width - 1, self.heigth - 1);
         let particle_ref = Rc::new(RefCell::new(particle));
 
         self.get_cell_mut(x, y).add_particle(particle_ref.clone());
         self.particles.push(particle_ref);
     }
 
+    /// Puts every particle back on the cell at its position, after particles were moved directly.
+    fn rebuild_cells(&mut self) {
+        for cell in self.cells.iter_mut() {
+            cell.particles.clear();
+        }
+        for particle_ref in self.particles.clone() {
+            let (x, y) = particle_ref
+                .borrow()
+                .get_render_position(self.width - 1, self.heigth - 1);
+            self.get_cell_mut(x, y).add_particle(particle_ref);
+        }
+    }
+
+    /// Removes every particle from the board.
+    pub fn clear_particles(&mut self) {
+        self.particles.clear();
+        for cell in self.cells.iter_mut() {
+            cell.particles.clear();
+        }
+    }
+
+    /// Rearranges the particles after a cut to a new shot, so that the new image forms quickly
+    /// instead of particles slowly migrating across the board. Call it once the attractors of
+    /// the new frame are set.
+    ///
+    /// # Arguments
+    /// - cut: What to do with the particles.
+    /// - spawn: How particles are placed again with `CutPolicy::Respawn`.
+    /// - previous: The attractors of the frame before the cut.
+    pub fn redistribute_particles(
+        &mut self,
+        cut: &CutConfig,
+        spawn: &SpawnConfig,
+        previous: &[Attractor],
+    ) {
+        // Particles fading out are not replaced.
+        let count = self
+            .particles
+            .iter()
+            .filter(|particle| !particle.borrow().is_dying())
+            .count();
+        self.targets_outdated = true;
+        match cut.policy {
+            CutPolicy::None => {}
+            CutPolicy::Respawn => {
+                self.clear_particles();
+                self.spawn_particles(count as u32, spawn);
+            }
+            CutPolicy::Teleport => {
+                let targets: Vec<Attractor> = self
+                    .attractors
+                    .iter()
+                    .filter(|attractor| attractor.mass > 0.0)
+                    .copied()
+                    .collect();
+                if targets.is_empty() {
+                    return;
+                }
+
+                // Particles fading out are removed right away.
+                self.particles
+                    .retain(|particle| !particle.borrow().is_dying());
+                for particle_ref in &self.particles {
+                    let target = targets[self.rng.random_range(0..targets.len())];
+                    let mut particle = particle_ref.borrow_mut();
+                    // Spread over the pixel, so that particles on the same attractor don't stack.
+                    particle.x = target.x as f32 + self.rng.random_range(-0.5..0.5);
+                    particle.y = target.y as f32 + self.rng.random_range(-0.5..0.5);
+                    particle.velocity = Force::default();
+                }
+                self.rebuild_cells();
+            }
+            CutPolicy::Explode => {
+                let (center_x, center_y) = centroid(previous).unwrap_or((
+                    (self.width - 1) as f32 / 2.0,
+                    (self.heigth - 1) as f32 / 2.0,
+                ));
+
+                for particle_ref in &self.particles {
+                    let mut particle = particle_ref.borrow_mut();
+                    let (dx, dy) = (particle.x - center_x, particle.y - center_y);
+                    let angle = if dx == 0.0 && dy == 0.0 {
+                        self.rng.random_range(0.0..TAU)
+                    } else {
+                        dy.atan2(dx)
+                    };
+                    let speed = cut.explode_speed * self.rng.random_range(0.5..1.5);
+                    particle.velocity = Force {
+                        x_component: angle.cos() * speed,
+                        y_component: angle.sin() * speed,
+                    };
+                }
+            }
+        }
+    }
+
+    /// Matches every particle to an attracting pixel for `assignment` mode, starting from the
+    /// targets they already have. See `assignment::assign`.
+    pub fn assign_targets(&mut self) {
+        let positions: Vec<(f32, f32)> = self
+            .particles
+            .iter()
+            .map(|particle| {
+                let particle = particle.borrow();
+                (particle.x, particle.y)
+            })
+            .collect();
+        let previous: Vec<Option<(u32, u32)>> = self
+            .particles
+            .iter()
+            .map(|particle| particle.borrow().target)
+            .collect();
+        let targets: Vec<(u32, u32)> = self
+            .attractors
+            .iter()
+            .filter(|attractor| attractor.mass > 0.0)
+            .map(|attractor| (attractor.x, attractor.y))
+            .collect();
+
+        let assigned = assignment::assign(
+            &positions,
+            &previous,
+            &targets,
+            self.physics.assignment_passes,
+            &mut self.rng,
+        );
+        for (particle, target) in self.particles.iter().zip(assigned) {
+            particle.borrow_mut().target = target;
+        }
+        self.targets_outdated = false;
+    }
+
+    /// Adds or removes a few particles, so that their number moves towards `population.ratio`
+    /// particles per attracting pixel. New particles appear on random attractors and fade in,
+    /// removed ones fade out first. Meant to run before every update.
+    pub fn control_population(&mut self, population: &PopulationConfig) {
+        let targets: Vec<Attractor> = self
+            .attractors
+            .iter()
+            .filter(|attractor| attractor.mass > 0.0)
+            .copied()
+            .collect();
+        let target = (population.ratio * targets.len() as f32).round() as usize;
+        let living: Vec<Rc<RefCell<Particle>>> = self
+            .particles
+            .iter()
+            .filter(|particle| !particle.borrow().is_dying())
+            .cloned()
+            .collect();
+        let fade = 1.0 / population.fade_steps.max(1) as f32;
+        let max_rate = population.max_rate as usize;
+
+        if living.len() < target {
+            for _ in 0..(target - living.len()).min(max_rate) {
+                let attractor = targets[self.rng.random_range(0..targets.len())];
+                let x = attractor.x as f32 + self.rng.random_range(-0.5..0.5);
+                let y = attractor.y as f32 + self.rng.random_range(-0.5..0.5);
+                self.insert_particle(Particle {
+                    x,
+                    y,
+                    opacity: 0.0,
+                    fade,
+                    // Born on its attractor, there is no need to match it with the others.
+                    target: Some((attractor.x, attractor.y)),
+                    ..Default::default()
+                });
+            }
+        } else if living.len() > target {
+            let deaths = (living.len() - target).min(max_rate);
+            for particle in living.choose_multiple(&mut self.rng, deaths) {
+                particle.borrow_mut().fade = -fade;
+            }
+        }
+
+        self.advance_fades();
+    }
+
+    /// Moves the opacity of fading particles on by one update, and removes those that faded out.
+    fn advance_fades(&mut self) {
+        let mut faded_out = vec![];
+        for particle_ref in &self.particles {
+            let mut particle = particle_ref.borrow_mut();
+            if particle.fade == 0.0 {
+                continue;
+            }
+
+            particle.opacity = (particle.opacity + particle.fade).clamp(0.0, 1.0);
+            if particle.opacity >= 1.0 {
+                particle.fade = 0.0;
+            } else if particle.opacity <= 0.0 && particle.is_dying() {
+                faded_out.push(particle_ref.clone());
+            }
+        }
+
+        for particle_ref in faded_out {
+            let (x, y) = particle_ref
+                .borrow()
+                .get_render_position(self.width - 1, self.heigth - 1);
+            self.get_cell_mut(x, y)
+                .remove_particle(particle_ref.clone());
+            self.particles
+                .retain(|particle| !Rc::ptr_eq(particle, &particle_ref));
+        }
+    }
+
+    /// The static field of every cell, in row-major order.
+    pub fn field(&self) -> Vec<Force<f32>> {
+        self.cells
+            .iter()
+            .map(|cell| cell.static_field.clone())
+            .collect()
+    }
+
+    /// Makes the static field move gradually from `previous` to the current one over the next
+    /// `steps` updates, instead of switching at once. Call this right after the static field has
+    /// been updated to the next frame, with the field from before that.
+    pub fn start_field_blend(
+        &mut self,
+        previous: Vec<Force<f32>>,
+        steps: u32,
+        interpolation: Interpolation,
+    ) {
+        if interpolation == Interpolation::None || steps == 0 {
+            self.field_blend = None;
+            return;
+        }
+
+        let to = self.field();
+        for (cell, force) in self.cells.iter_mut().zip(&previous) {
+            cell.static_field = force.clone();
+        }
+        self.field_blend = Some(FieldBlend {
+            from: previous,
+            to,
+            interpolation,
+            step: 0,
+            steps,
+        });
+    }
+
+    /// Moves the static field one update further along the blend in progress.
+    fn advance_field_blend(&mut self) {
+        let Some(blend) = &mut self.field_blend else {
+            return;
+        };
+
+        blend.step += 1;
+        if blend.step >= blend.steps {
+            // End exactly on the field of the current frame.
+            let to = self.field_blend.take().unwrap().to;
+            for (cell, force) in self.cells.iter_mut().zip(to) {
+                cell.static_field = force;
+            }
+            return;
+        }
+
+        let amount = blend
+            .interpolation
+            .apply(blend.step as f32 / blend.steps as f32);
+        for ((cell, from), to) in self.cells.iter_mut().zip(&blend.from).zip(&blend.to) {
+            cell.static_field = from.clone() + (to.clone() - from.clone()) * amount;
+        }
+    }
+
     pub fn get_cell(&self, x: u32, y: u32) -> &BoardCell {
-        return &self.cells[(x + y * self.width) as usize];
+        &self.cells[(x + y * self.width) as usize]
     }
 
     pub fn get_cell_mut(&mut self, x: u32, y: u32) -> &mut BoardCell {
-        return &mut self.cells[(x + y * self.width) as usize];
+        &mut self.cells[(x + y * self.width) as usize]
     }
 
     pub fn is_field_corrupted(&self) -> bool {
@@ -155,7 +609,7 @@ impl Board {
                 return true;
             }
         }
-        return false;
+        false
     }
 
     pub fn draw_static_field(&self, pixels: &mut [u8]) {
@@ -173,81 +627,228 @@ impl Board {
             } else if cell.static_field.x_component >= 0.0 && cell.static_field.y_component <= 0.0 {
                 [0xff, 0xff, 0, 0xff]
             } else {
-                println!("[ERROR] Vector somehow breaks laws of physics. Cell[{}][{}] has force (x: {}, y:{})", cell.x, cell.y, cell.static_field.x_component, cell.static_field.y_component);
+                warn!(
+                    x = cell.x,
+                    y = cell.y,
+                    force_x = cell.static_field.x_component,
+                    force_y = cell.static_field.y_component,
+                    "Vector somehow breaks laws of physics"
+                );
                 [0xff, 0xff, 0xff, 0xff]
             };
             pixel.copy_from_slice(&color);
         }
     }
 
-    pub fn draw_particles(&self, pixels: &mut [u8]) {
-        for (cell, pixel) in self.cells.iter().zip(pixels.chunks_exact_mut(4)) {
-            // let color = if cell.particles.len() <= 0 {
-            //     [0, 0, 0, 0xff]
-            // } else {
-            //     [0xff, 0xff, 0xff, (0xff_u8 / 4).saturating_mul(cell.particles.len() as u8)]
-            // };
-            let color = if cell.particles.len() == 0 {
-                [0, 0, 0, 0xff]
+    /// Draws every attractor with a brightness relative to the heaviest one. Attractors with a
+    /// negative mass (repelling pixels) are drawn in red.
+    pub fn draw_attractors(&self, pixels: &mut [u8]) {
+        for pixel in pixels.chunks_exact_mut(4) {
+            pixel.copy_from_slice(&[0, 0, 0, 0xff]);
+        }
+
+        let max_mass = self
+            .attractors
+            .iter()
+            .map(|attractor| attractor.mass.abs())
+            .fold(0.0, f32::max);
+        for attractor in &self.attractors {
+            let value = (attractor.mass.abs() / max_mass * 255.0) as u8;
+            let color = if attractor.mass < 0.0 {
+                [value, 0, 0, 0xff]
             } else {
-                [0xff, 0xff, 0xff, 0xff]
+                [value, value, value, 0xff]
             };
+            let index = ((attractor.x + attractor.y * self.width) * 4) as usize;
+            pixels[index..index + 4].copy_from_slice(&color);
+        }
+    }
 
-            pixel.copy_from_slice(&color);
+    pub fn draw_particles(&self, pixels: &mut [u8], render: &RenderConfig) {
+        for (cell, pixel) in self.cells.iter().zip(pixels.chunks_exact_mut(4)) {
+            // Particles count for their opacity, so that those fading in or out are dimmer.
+            let coverage = cell
+                .particles
+                .iter()
+                .map(|particle| particle.borrow().opacity)
+                .sum();
+
+            shade_pixel(pixel, coverage, render);
         }
     }
 
-    pub fn load_static_field(
-        &mut self,
-        field_path: &std::path::Path,
-    ) -> Result<(), std::io::Error> {
-        let mut file = File::open(field_path)?;
-        let mut bytes: Vec<u8> = vec![];
-        file.read_to_end(&mut bytes)?;
-        let mut reader = Cursor::new(bytes);
+    /// Draws the particles onto a canvas that can be larger than the board. Each particle is
+    /// splatted at its sub-pixel position with a tent filter, so upscaled frames stay smooth
+    /// instead of showing the board's pixel grid.
+    ///
+    /// # Arguments
+    /// - pixels: RGBA buffer holding `canvas_width * canvas_height` pixels.
+    /// - canvas_width, canvas_height: The dimensions of the canvas.
+    /// - splat_radius: How far a particle reaches, in canvas pixels. Clamped to at least 1.
+    /// - render: The style to draw with.
+    pub fn draw_particles_upscaled(
+        &self,
+        pixels: &mut [u8],
+        canvas_width: u32,
+        canvas_height: u32,
+        splat_radius: f32,
+        render: &RenderConfig,
+    ) {
+        let scale_x = canvas_width as f32 / self.width as f32;
+        let scale_y = canvas_height as f32 / self.heigth as f32;
+        let radius = splat_radius.max(1.0);
+        let mut coverage = vec![0.0f32; (canvas_width * canvas_height) as usize];
 
-        for y in 0..self.heigth {
-            for x in 0..self.width {
-                let force_x = reader.read_f32::<LittleEndian>()?;
-                let force_y = reader.read_f32::<LittleEndian>()?;
-                self.get_cell_mut(x, y).static_field = Force {
-                    x_component: force_x,
-                    y_component: force_y,
-                };
+        for particle_ref in &self.particles {
+            let particle = particle_ref.borrow();
+            // Same as `draw_particles`, particles that left the board are drawn on its edge.
+            let board_x = particle.x.clamp(0.0, (self.width - 1) as f32);
+            let board_y = particle.y.clamp(0.0, (self.heigth - 1) as f32);
+
+            // Board pixels are centered on integer coordinates, canvas pixels on half-integers.
+            let center_x = (board_x + 0.5) * scale_x - 0.5;
+            let center_y = (board_y + 0.5) * scale_y - 0.5;
+
+            let min_x = (center_x - radius).floor().max(0.0) as u32;
+            let max_x = ((center_x + radius).ceil() as u32).min(canvas_width - 1);
+            let min_y = (center_y - radius).floor().max(0.0) as u32;
+            let max_y = ((center_y + radius).ceil() as u32).min(canvas_height - 1);
+
+            for y in min_y..=max_y {
+                let weight_y = 1.0 - (y as f32 - center_y).abs() / radius;
+                if weight_y <= 0.0 {
+                    continue;
+                }
+                for x in min_x..=max_x {
+                    let weight_x = 1.0 - (x as f32 - center_x).abs() / radius;
+                    if weight_x > 0.0 {
+                        coverage[(x + y * canvas_width) as usize] +=
+                            weight_x * weight_y * particle.opacity;
+                    }
+                }
             }
         }
 
-        return Ok(());
+        for (value, pixel) in coverage.iter().zip(pixels.chunks_exact_mut(4)) {
+            shade_pixel(pixel, *value, render);
+        }
     }
 
-    pub fn save_field(&self, path: &std::path::Path) -> Result<(), Error> {
-        let mut file = File::create(path)?;
-        for y in 0..self.heigth {
-            for x in 0..self.width {
-                let force = self.get_cell(x, y).static_field.clone();
-                file.write_f32::<LittleEndian>(force.x_component)?;
-                file.write_f32::<LittleEndian>(force.y_component)?;
+    /// Loads a static field saved by `save_field`.
+    ///
+    /// # Returns
+    /// `true` if the field was loaded, `false` (leaving the board as it is) if the file was
+    /// generated from other parameters or attractors than those of `key`, or by an older version.
+    /// Fails if the file cannot be read, is cut short or holds invalid forces, also leaving the
+    /// board as it is.
+    pub fn load_static_field(&mut self, field_path: &Path, key: u64) -> Result<bool> {
+        let bytes = std::fs::read(field_path).map_err(|err| Error::io(field_path, err))?;
+        let corrupt = |reason: &str| Error::CorruptField {
+            path: field_path.to_path_buf(),
+            reason: reason.to_string(),
+        };
+        let cut_short = |_| corrupt("the file is cut short");
+        let mut reader = Cursor::new(bytes);
+
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic).map_err(cut_short)?;
+        if &magic != FIELD_MAGIC || reader.read_u64::<LittleEndian>().map_err(cut_short)? != key {
+            return Ok(false);
+        }
+
+        // Read in full and checked before anything is replaced, so that a corrupt file leaves the
+        // field of the board intact.
+        let mut field = Vec::with_capacity(self.cells.len());
+        for _ in 0..self.cells.len() {
+            let force = Force {
+                x_component: reader.read_f32::<LittleEndian>().map_err(cut_short)?,
+                y_component: reader.read_f32::<LittleEndian>().map_err(cut_short)?,
+            };
+            if !force.x_component.is_finite() || !force.y_component.is_finite() {
+                return Err(corrupt("it holds invalid forces"));
             }
+            field.push(force);
         }
-        return Ok(());
+
+        for (cell, force) in self.cells.iter_mut().zip(field) {
+            cell.static_field = force;
+        }
+        Ok(true)
     }
 
-    pub fn to_field(self) -> Vec<Force<f32>> {
-        return self
-            .cells
+    pub fn save_field(&self, path: &Path) -> Result<()> {
+        write_field(
+            path,
+            self.field_key,
+            self.cells.iter().map(|cell| &cell.static_field),
+        )
+    }
+
+    pub fn into_field(self) -> Vec<Force<f32>> {
+        self.cells
             .into_iter()
             .map(|cell| cell.static_field)
-            .collect();
+            .collect()
     }
 }
 
+/// Writes a static field in the format read by `Board::load_static_field`. The forces must be in
+/// row-major order.
+pub fn write_field<'a>(
+    path: &Path,
+    key: u64,
+    forces: impl Iterator<Item = &'a Force<f32>>,
+) -> Result<()> {
+    let write = || -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(FIELD_MAGIC)?;
+        file.write_u64::<LittleEndian>(key)?;
+        for force in forces {
+            file.write_f32::<LittleEndian>(force.x_component)?;
+            file.write_f32::<LittleEndian>(force.y_component)?;
+        }
+        file.flush()
+    };
+    write().map_err(|err| Error::io(path, err))
+}
+
+/// The center of the attracting pixels of `attractors`, if there are any.
+fn centroid(attractors: &[Attractor]) -> Option<(f32, f32)> {
+    let positive = attractors.iter().filter(|attractor| attractor.mass > 0.0);
+    let (count, sum_x, sum_y) = positive.fold((0, 0.0, 0.0), |(count, sum_x, sum_y), attractor| {
+        (
+            count + 1,
+            sum_x + attractor.x as f32,
+            sum_y + attractor.y as f32,
+        )
+    });
+    (count > 0).then(|| (sum_x / count as f32, sum_y / count as f32))
+}
+
+/// Writes a grayscale pixel for a particle `coverage` (roughly the number of particles on it),
+/// following the render style.
+fn shade_pixel(pixel: &mut [u8], coverage: f32, render: &RenderConfig) {
+    let intensity = match render.style {
+        RenderStyle::Solid => coverage.min(1.0),
+        RenderStyle::Density => (coverage / render.density_saturation).min(1.0),
+        RenderStyle::Trails => {
+            // The buffer still holds the previous frame.
+            let previous = pixel[0] as f32 / 255.0;
+            coverage.min(1.0).max(previous * render.trail_decay)
+        }
+    };
+
+    let intensity = (intensity * 255.0).round() as u8;
+    pixel.copy_from_slice(&[intensity, intensity, intensity, 0xff]);
+}
+
 impl BoardCell {
     pub fn add_particle(&mut self, particle: Rc<RefCell<Particle>>) {
         self.particles.push(particle);
     }
 
     pub fn remove_particle(&mut self, particle: Rc<RefCell<Particle>>) {
-        self.particles.retain(|x| !Rc::ptr_eq(&x, &particle));
+        self.particles.retain(|x| !Rc::ptr_eq(x, &particle));
     }
 
     // pub fn remove_particles(&mut self, particles: Vec<Rc<RefCell<Particle>>>) {
@@ -263,3 +864,246 @@ impl BoardCell {
     //         .collect()
     // }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ForceLaw;
+
+    const FORCE_LAWS: [ForceLaw; 3] = [
+        ForceLaw::InverseCube,
+        ForceLaw::InverseSquare,
+        ForceLaw::Inverse,
+    ];
+
+    fn physics(force_law: ForceLaw) -> PhysicsConfig {
+        PhysicsConfig {
+            force_law,
+            ..PhysicsConfig::default()
+        }
+    }
+
+    fn attractor(x: u32, y: u32) -> Attractor {
+        Attractor { x, y, mass: 10.0 }
+    }
+
+    /// A 64x64 board with the static field of `attractors`.
+    fn board(attractors: Vec<Attractor>, physics: PhysicsConfig) -> Board {
+        let mut board = Board::new(64, 64, physics);
+        board.set_attractors(attractors);
+        board.generate_static_field();
+        board
+    }
+
+    /// The pixels at `radius` from (32, 32), symmetric under rotations by a quarter turn and
+    /// reflections across both axes and diagonals.
+    fn ring(radius: f32) -> Vec<Attractor> {
+        (0..64)
+            .flat_map(|y| (0..64).map(move |x| (x, y)))
+            .filter(|&(x, y)| {
+                let distance = (x as f32 - 32.0).hypot(y as f32 - 32.0);
+                (distance - radius).abs() < 0.5
+            })
+            .map(|(x, y)| attractor(x, y))
+            .collect()
+    }
+
+    fn magnitude(force: &Force<f32>) -> f32 {
+        force.x_component.hypot(force.y_component)
+    }
+
+    #[test]
+    fn fields_add_up() {
+        let first = vec![attractor(10, 12), attractor(40, 50)];
+        let second = vec![attractor(33, 7), attractor(20, 20), attractor(60, 1)];
+        for law in FORCE_LAWS {
+            let both = board([first.clone(), second.clone()].concat(), physics(law)).field();
+            let first = board(first.clone(), physics(law)).field();
+            let second = board(second.clone(), physics(law)).field();
+            for ((both, first), second) in both.iter().zip(first).zip(second) {
+                let sum = first + second;
+                assert!((both.x_component - sum.x_component).abs() <= 1e-6);
+                assert!((both.y_component - sum.y_component).abs() <= 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn field_of_a_point() {
+        for law in FORCE_LAWS {
+            let physics = physics(law);
+            let board = board(vec![attractor(20, 30)], physics);
+            for cell in &board.cells {
+                let (rx, ry) = (20.0 - cell.x as f32, 30.0 - cell.y as f32);
+                let distance = rx.hypot(ry);
+                if distance == 0.0 {
+                    assert_eq!(magnitude(&cell.static_field), 0.0);
+                    continue;
+                }
+                // G m M / r^n, along the unit vector towards the attractor.
+                let strength = physics.g * physics.particle_mass * 10.0
+                    / distance.powi(law.exponent())
+                    / distance;
+                let field = &cell.static_field;
+                assert!((field.x_component - strength * rx).abs() <= 1e-4 * strength * distance);
+                assert!((field.y_component - strength * ry).abs() <= 1e-4 * strength * distance);
+            }
+        }
+    }
+
+    #[test]
+    fn field_of_a_ring_is_symmetric() {
+        for law in FORCE_LAWS {
+            let board = board(ring(20.0), physics(law));
+            let scale = magnitude(&board.get_cell(32, 32 + 19).static_field);
+            assert!(magnitude(&board.get_cell(32, 32).static_field) <= 1e-5 * scale);
+            for offset in 1..32 {
+                let right = &board.get_cell(32 + offset, 32).static_field;
+                let left = &board.get_cell(32 - offset, 32).static_field;
+                let below = &board.get_cell(32, 32 + offset).static_field;
+                assert!(right.y_component.abs() <= 1e-5 * scale);
+                assert!((right.x_component + left.x_component).abs() <= 1e-5 * scale);
+                assert!((right.x_component - below.y_component).abs() <= 1e-5 * scale);
+            }
+        }
+    }
+
+    #[test]
+    fn field_of_a_ring_follows_gauss_law() {
+        // With F ~ 1/r, a ring in two dimensions is like a shell in three: it pulls what is
+        // outside as if its mass sat at its center, and (nearly) nothing inside.
+        let physics = physics(ForceLaw::Inverse);
+        let attractors = ring(20.0);
+        let total_mass: f32 = attractors.iter().map(|attractor| attractor.mass).sum();
+        let board = board(attractors, physics);
+        for distance in [26, 29, 31] {
+            let expected = physics.g * physics.particle_mass * total_mass / distance as f32;
+            let field = &board.get_cell(32 + distance, 32).static_field;
+            assert!(field.x_component < 0.0);
+            assert!(
+                (magnitude(field) - expected).abs() <= 0.05 * expected,
+                "{} vs {} at {}",
+                magnitude(field),
+                expected,
+                distance
+            );
+        }
+        for distance in [3, 6, 10] {
+            let outside = physics.g * physics.particle_mass * total_mass / 26.0;
+            let field = &board.get_cell(32 + distance, 32).static_field;
+            assert!(magnitude(field) <= 0.05 * outside);
+        }
+    }
+
+    #[test]
+    fn falling_particles_keep_their_energy() {
+        // A particle falling from rest gains as much kinetic energy as it loses potential energy,
+        // -G m M / r with F ~ 1/r^2.
+        let physics = PhysicsConfig {
+            g: 1.0,
+            ..physics(ForceLaw::InverseSquare)
+        };
+        let mut board = board(vec![attractor(8, 32)], physics);
+        board.add_particle(48, 32);
+        while board.particles[0].borrow().x > 24.0 {
+            board.update();
+        }
+
+        let particle = board.particles[0].borrow();
+        let potential = |x: f32| -physics.g * physics.particle_mass * 10.0 / (x - 8.0);
+        let lost = potential(48.0) - potential(particle.x);
+        let speed = magnitude(&particle.velocity);
+        let kinetic = 0.5 * physics.particle_mass * speed * speed;
+        assert_eq!(particle.y, 32.0);
+        assert!(
+            (kinetic - lost).abs() <= 0.05 * lost,
+            "{} vs {}",
+            kinetic,
+            lost
+        );
+    }
+
+    #[test]
+    fn saved_fields_load_back() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("ring.field");
+        let saved = board(ring(20.0), PhysicsConfig::default());
+        saved.save_field(&path).unwrap();
+
+        let mut loaded = Board::new(64, 64, PhysicsConfig::default());
+        loaded.set_attractors(ring(20.0));
+        assert_eq!(loaded.field_key, saved.field_key);
+        assert!(loaded.load_static_field(&path, loaded.field_key).unwrap());
+        for (loaded, saved) in loaded.cells.iter().zip(&saved.cells) {
+            assert_eq!(
+                loaded.static_field.x_component,
+                saved.static_field.x_component
+            );
+            assert_eq!(
+                loaded.static_field.y_component,
+                saved.static_field.y_component
+            );
+        }
+    }
+
+    #[test]
+    fn fields_of_other_attractors_are_not_loaded() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("ring.field");
+        board(ring(20.0), PhysicsConfig::default())
+            .save_field(&path)
+            .unwrap();
+
+        let mut other = Board::new(64, 64, PhysicsConfig::default());
+        other.set_attractors(ring(10.0));
+        assert!(!other.load_static_field(&path, other.field_key).unwrap());
+        assert!(other
+            .cells
+            .iter()
+            .all(|cell| magnitude(&cell.static_field) == 0.0));
+
+        std::fs::write(&path, b"not a field at all").unwrap();
+        assert!(!other.load_static_field(&path, other.field_key).unwrap());
+    }
+
+    #[test]
+    fn truncated_fields_are_corrupt() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("ring.field");
+        let saved = board(ring(20.0), PhysicsConfig::default());
+        saved.save_field(&path).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &bytes[..bytes.len() / 2]).unwrap();
+
+        let mut loaded = Board::new(64, 64, PhysicsConfig::default());
+        loaded.set_attractors(ring(20.0));
+        assert!(matches!(
+            loaded.load_static_field(&path, loaded.field_key),
+            Err(Error::CorruptField { .. })
+        ));
+        assert!(loaded
+            .cells
+            .iter()
+            .all(|cell| magnitude(&cell.static_field) == 0.0));
+    }
+
+    #[test]
+    fn fields_with_invalid_forces_are_corrupt() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("ring.field");
+        let mut saved = board(ring(20.0), PhysicsConfig::default());
+        saved.cells[100].static_field.y_component = f32::NAN;
+        saved.save_field(&path).unwrap();
+
+        let mut loaded = Board::new(64, 64, PhysicsConfig::default());
+        loaded.set_attractors(ring(20.0));
+        assert!(matches!(
+            loaded.load_static_field(&path, loaded.field_key),
+            Err(Error::CorruptField { .. })
+        ));
+        assert!(loaded
+            .cells
+            .iter()
+            .all(|cell| magnitude(&cell.static_field) == 0.0));
+    }
+}
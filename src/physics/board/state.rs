@@ -0,0 +1,265 @@
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    io::{Error, ErrorKind, Read, Write},
+    rc::Rc,
+};
+
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+use super::{Board, BoardCell, FieldBlend};
+use crate::{
+    config::{Interpolation, PhysicsConfig},
+    physics::{force::Force, particle::Particle, Attractor},
+};
+
+impl Board {
+    /// Writes everything that changes while the simulation runs: the particles (and the order
+    /// they are in on each cell), the random number generator, the attractors and the static
+    /// field, including a blend in progress. `read_state` restores a board that continues
+    /// exactly the same way.
+    pub fn write_state(&self, writer: &mut impl Write) -> Result<(), Error> {
+        writer.write_u32::<LittleEndian>(self.width)?;
+        writer.write_u32::<LittleEndian>(self.heigth)?;
+
+        writer.write_all(&self.rng.get_seed())?;
+        writer.write_u64::<LittleEndian>(self.rng.get_stream())?;
+        writer.write_u128::<LittleEndian>(self.rng.get_word_pos())?;
+
+        writer.write_u64::<LittleEndian>(self.field_key)?;
+        writer.write_u8(self.targets_outdated as u8)?;
+
+        writer.write_u64::<LittleEndian>(self.attractors.len() as u64)?;
+        for attractor in &self.attractors {
+            writer.write_u32::<LittleEndian>(attractor.x)?;
+            writer.write_u32::<LittleEndian>(attractor.y)?;
+            writer.write_f32::<LittleEndian>(attractor.mass)?;
+        }
+
+        write_forces(writer, self.cells.iter().map(|cell| &cell.static_field))?;
+        match &self.field_blend {
+            None => writer.write_u8(0)?,
+            Some(blend) => {
+                writer.write_u8(1)?;
+                writer.write_u8(interpolation_id(blend.interpolation))?;
+                writer.write_u32::<LittleEndian>(blend.step)?;
+                writer.write_u32::<LittleEndian>(blend.steps)?;
+                write_forces(writer, blend.from.iter())?;
+                write_forces(writer, blend.to.iter())?;
+            }
+        }
+
+        writer.write_u64::<LittleEndian>(self.next_particle_id)?;
+        writer.write_u64::<LittleEndian>(self.particles.len() as u64)?;
+        for particle_ref in &self.particles {
+            let particle = particle_ref.borrow();
+            writer.write_u64::<LittleEndian>(particle.id)?;
+            writer.write_f32::<LittleEndian>(particle.x)?;
+            writer.write_f32::<LittleEndian>(particle.y)?;
+            writer.write_f32::<LittleEndian>(particle.velocity.x_component)?;
+            writer.write_f32::<LittleEndian>(particle.velocity.y_component)?;
+            writer.write_f32::<LittleEndian>(particle.opacity)?;
+            writer.write_f32::<LittleEndian>(particle.fade)?;
+            match particle.target {
+                None => writer.write_u8(0)?,
+                Some((x, y)) => {
+                    writer.write_u8(1)?;
+                    writer.write_u32::<LittleEndian>(x)?;
+                    writer.write_u32::<LittleEndian>(y)?;
+                }
+            }
+        }
+
+        // Particles are drawn in the order they are in on their cell, which changes the rounding
+        // of faded particles, so the order is kept too.
+        let indices: HashMap<*const RefCell<Particle>, usize> = self
+            .particles
+            .iter()
+            .enumerate()
+            .map(|(index, particle)| (Rc::as_ptr(particle), index))
+            .collect();
+        for cell in &self.cells {
+            writer.write_u32::<LittleEndian>(cell.particles.len() as u32)?;
+            for particle_ref in &cell.particles {
+                let index = *indices
+                    .get(&Rc::as_ptr(particle_ref))
+                    .ok_or_else(|| invalid_data("a cell holds a particle not on the board"))?;
+                writer.write_u32::<LittleEndian>(index as u32)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Restores a board written by `write_state`, simulated with `physics`. Counts that don't fit
+    /// the board are refused as invalid data before anything is allocated for them, and the rest
+    /// only grows as it is read, so a corrupt file ends in an error instead of a huge allocation.
+    pub fn read_state(reader: &mut impl Read, physics: PhysicsConfig) -> Result<Board, Error> {
+        let width = reader.read_u32::<LittleEndian>()?;
+        let heigth = reader.read_u32::<LittleEndian>()?;
+        // Cells are indexed with a u32.
+        let cell_count = width as u64 * heigth as u64;
+        if cell_count == 0 || cell_count > u32::MAX as u64 {
+            return Err(invalid_data("the board has an invalid size"));
+        }
+        let cell_count = cell_count as usize;
+
+        let mut seed = [0u8; 32];
+        reader.read_exact(&mut seed)?;
+        let mut rng = ChaCha8Rng::from_seed(seed);
+        rng.set_stream(reader.read_u64::<LittleEndian>()?);
+        rng.set_word_pos(reader.read_u128::<LittleEndian>()?);
+
+        let field_key = reader.read_u64::<LittleEndian>()?;
+        let targets_outdated = reader.read_u8()? != 0;
+
+        // Attractors are pixels of the frame, so there is at most one per cell.
+        let attractor_count = reader.read_u64::<LittleEndian>()?;
+        if attractor_count > cell_count as u64 {
+            return Err(invalid_data("there are more attractors than cells"));
+        }
+        let mut attractors = vec![];
+        for _ in 0..attractor_count {
+            let attractor = Attractor {
+                x: reader.read_u32::<LittleEndian>()?,
+                y: reader.read_u32::<LittleEndian>()?,
+                mass: reader.read_f32::<LittleEndian>()?,
+            };
+            if attractor.x >= width || attractor.y >= heigth {
+                return Err(invalid_data("an attractor is outside the board"));
+            }
+            attractors.push(attractor);
+        }
+
+        let field = read_forces(reader, cell_count)?;
+        let field_blend = match reader.read_u8()? {
+            0 => None,
+            _ => Some(FieldBlend {
+                interpolation: interpolation_from_id(reader.read_u8()?)?,
+                step: reader.read_u32::<LittleEndian>()?,
+                steps: reader.read_u32::<LittleEndian>()?,
+                from: read_forces(reader, cell_count)?,
+                to: read_forces(reader, cell_count)?,
+            }),
+        };
+
+        let next_particle_id = reader.read_u64::<LittleEndian>()?;
+        // Cells refer to particles with a u32 index.
+        let particle_count = reader.read_u64::<LittleEndian>()?;
+        if particle_count > u32::MAX as u64 {
+            return Err(invalid_data(
+                "there are more particles than cells can refer to",
+            ));
+        }
+        let mut particles = vec![];
+        for _ in 0..particle_count {
+            let id = reader.read_u64::<LittleEndian>()?;
+            let x = reader.read_f32::<LittleEndian>()?;
+            let y = reader.read_f32::<LittleEndian>()?;
+            let velocity = Force {
+                x_component: reader.read_f32::<LittleEndian>()?,
+                y_component: reader.read_f32::<LittleEndian>()?,
+            };
+            let opacity = reader.read_f32::<LittleEndian>()?;
+            let fade = reader.read_f32::<LittleEndian>()?;
+            let target = match reader.read_u8()? {
+                0 => None,
+                _ => Some((
+                    reader.read_u32::<LittleEndian>()?,
+                    reader.read_u32::<LittleEndian>()?,
+                )),
+            };
+            particles.push(Rc::new(RefCell::new(Particle {
+                id,
+                x,
+                y,
+                velocity,
+                opacity,
+                fade,
+                target,
+            })));
+        }
+
+        // Every particle is on a single cell.
+        let mut placed = 0u64;
+        let mut cells = Vec::with_capacity(cell_count);
+        for (index, static_field) in field.into_iter().enumerate() {
+            let mut cell = BoardCell::new(index as u32 % width, index as u32 / width);
+            cell.static_field = static_field;
+            let count = reader.read_u32::<LittleEndian>()?;
+            placed += count as u64;
+            if placed > particle_count {
+                return Err(invalid_data("the cells hold more particles than the board"));
+            }
+            for _ in 0..count {
+                let particle = reader.read_u32::<LittleEndian>()? as usize;
+                let particle = particles
+                    .get(particle)
+                    .ok_or_else(|| invalid_data("a cell holds a particle not on the board"))?;
+                cell.add_particle(particle.clone());
+            }
+            cells.push(cell);
+        }
+
+        Ok(Board {
+            width,
+            heigth,
+            cells,
+            particles,
+            rng,
+            physics,
+            attractors,
+            field_key,
+            field_blend,
+            targets_outdated,
+            next_particle_id,
+        })
+    }
+}
+
+fn write_forces<'a>(
+    writer: &mut impl Write,
+    forces: impl Iterator<Item = &'a Force<f32>>,
+) -> Result<(), Error> {
+    for force in forces {
+        writer.write_f32::<LittleEndian>(force.x_component)?;
+        writer.write_f32::<LittleEndian>(force.y_component)?;
+    }
+    Ok(())
+}
+
+/// Reads the forces of `count` cells. The vector grows as they are read, so that a file cut short
+/// fails before the whole field is allocated.
+fn read_forces(reader: &mut impl Read, count: usize) -> Result<Vec<Force<f32>>, Error> {
+    let mut forces = Vec::with_capacity(count.min(1 << 16));
+    for _ in 0..count {
+        forces.push(Force {
+            x_component: reader.read_f32::<LittleEndian>()?,
+            y_component: reader.read_f32::<LittleEndian>()?,
+        });
+    }
+    Ok(forces)
+}
+
+fn interpolation_id(interpolation: Interpolation) -> u8 {
+    match interpolation {
+        Interpolation::None => 0,
+        Interpolation::Linear => 1,
+        Interpolation::EaseInOut => 2,
+    }
+}
+
+fn interpolation_from_id(id: u8) -> Result<Interpolation, Error> {
+    match id {
+        0 => Ok(Interpolation::None),
+        1 => Ok(Interpolation::Linear),
+        2 => Ok(Interpolation::EaseInOut),
+        _ => Err(invalid_data("unknown interpolation")),
+    }
+}
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
@@ -1,11 +1,8 @@
 use super::force::Force;
+use crate::config::{ForceLaw, PhysicsConfig};
 
-pub const ATTRACTOR_MASS: f32 = 10.0;
-pub const PARTICLE_MASS: f32 = 1.0;
-
-pub const TIMESTEP: f32 = 1.0;
-
-const G: f32 = 1.0 / 1000.0;
+/// The force the body of `mass2` at (`x2`, `y2`) exerts on the body of `mass1` at (`x1`, `y1`),
+/// pointing from the first towards the second. Bodies on the same pixel exert no force.
 pub fn gravitational_force(
     x1: u32,
     y1: u32,
@@ -13,6 +10,7 @@ pub fn gravitational_force(
     x2: u32,
     y2: u32,
     mass2: f32,
+    physics: &PhysicsConfig,
 ) -> Force<f32> {
     let rx = (x2 as i32) - (x1 as i32);
     let ry = (y2 as i32) - (y1 as i32);
@@ -21,14 +19,135 @@ pub fn gravitational_force(
     if radius_squared == 0.0 {
         return Force::default();
     }
-    let cos_alpha = (rx as f32) / (radius_squared as f32);
-    let sin_alpha = (ry as f32) / (radius_squared as f32);
+    let cos_alpha = (rx as f32) / radius_squared;
+    let sin_alpha = (ry as f32) / radius_squared;
 
     // LAW IS:
     // F = G * m1 * m2 / r^2
     // Where G is a constant. We'll set this constant however we want, as to fine-tune our simulation.
+    // The power of r depends on the configured force law. Note that cos_alpha and sin_alpha are
+    // already divided by r once more, so the default law ends up as F ~ 1/r^3.
+    let falloff = match physics.force_law {
+        ForceLaw::InverseCube => radius_squared,
+        ForceLaw::InverseSquare => radius_squared.sqrt(),
+        ForceLaw::Inverse => 1.0,
+    };
     Force {
-        x_component: G * mass1 * mass2 / radius_squared * cos_alpha,
-        y_component: G * mass1 * mass2 / (radius_squared) * sin_alpha,
+        x_component: physics.g * mass1 * mass2 / falloff * cos_alpha,
+        y_component: physics.g * mass1 * mass2 / falloff * sin_alpha,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    const FORCE_LAWS: [ForceLaw; 3] = [
+        ForceLaw::InverseCube,
+        ForceLaw::InverseSquare,
+        ForceLaw::Inverse,
+    ];
+
+    fn physics(force_law: ForceLaw) -> PhysicsConfig {
+        PhysicsConfig {
+            force_law,
+            ..PhysicsConfig::default()
+        }
+    }
+
+    fn force_laws() -> impl Strategy<Value = ForceLaw> {
+        prop::sample::select(FORCE_LAWS.to_vec())
+    }
+
+    /// Two different pixels of a 256x256 board.
+    fn pixel_pairs() -> impl Strategy<Value = ((u32, u32), (u32, u32))> {
+        ((0u32..256, 0u32..256), (0u32..256, 0u32..256)).prop_filter("same pixel", |(a, b)| a != b)
+    }
+
+    #[test]
+    fn points_towards_the_attractor() {
+        let physics = physics(ForceLaw::default());
+        let right = gravitational_force(10, 10, 1.0, 15, 10, 10.0, &physics);
+        assert!(right.x_component > 0.0 && right.y_component == 0.0);
+        let left = gravitational_force(10, 10, 1.0, 5, 10, 10.0, &physics);
+        assert!(left.x_component < 0.0 && left.y_component == 0.0);
+        // Rows go down the image.
+        let below = gravitational_force(10, 10, 1.0, 10, 12, 10.0, &physics);
+        assert!(below.x_component == 0.0 && below.y_component > 0.0);
+        let above = gravitational_force(10, 10, 1.0, 10, 3, 10.0, &physics);
+        assert!(above.x_component == 0.0 && above.y_component < 0.0);
+    }
+
+    #[test]
+    fn negative_mass_repels() {
+        let physics = physics(ForceLaw::default());
+        let force = gravitational_force(10, 10, 1.0, 15, 10, -10.0, &physics);
+        assert!(force.x_component < 0.0);
+    }
+
+    #[test]
+    fn no_force_on_the_same_pixel() {
+        for law in FORCE_LAWS {
+            let force = gravitational_force(7, 9, 1.0, 7, 9, 10.0, &physics(law));
+            assert_eq!((force.x_component, force.y_component), (0.0, 0.0));
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn follows_the_displacement(((x1, y1), (x2, y2)) in pixel_pairs(), law in force_laws()) {
+            let force = gravitational_force(x1, y1, 1.0, x2, y2, 10.0, &physics(law));
+            let (rx, ry) = (x2 as f32 - x1 as f32, y2 as f32 - y1 as f32);
+            let magnitude = force.x_component.hypot(force.y_component);
+            let cross = force.x_component * ry - force.y_component * rx;
+            let dot = force.x_component * rx + force.y_component * ry;
+            prop_assert!(cross.abs() <= 1e-4 * magnitude * rx.hypot(ry));
+            prop_assert!(dot > 0.0);
+        }
+
+        #[test]
+        fn magnitude_follows_the_law(
+            ((x1, y1), (x2, y2)) in pixel_pairs(),
+            law in force_laws(),
+            mass1 in 0.1f32..10.0,
+            mass2 in 0.1f32..10.0,
+        ) {
+            let physics = physics(law);
+            let force = gravitational_force(x1, y1, mass1, x2, y2, mass2, &physics);
+            let distance = (x2 as f32 - x1 as f32).hypot(y2 as f32 - y1 as f32);
+            let expected = physics.g * mass1 * mass2 / distance.powi(law.exponent());
+            let magnitude = force.x_component.hypot(force.y_component);
+            prop_assert!((magnitude - expected).abs() <= 1e-4 * expected);
+        }
+
+        #[test]
+        fn actions_are_opposite(
+            ((x1, y1), (x2, y2)) in pixel_pairs(),
+            law in force_laws(),
+            mass1 in 0.1f32..10.0,
+            mass2 in 0.1f32..10.0,
+        ) {
+            let physics = physics(law);
+            let action = gravitational_force(x1, y1, mass1, x2, y2, mass2, &physics);
+            let reaction = gravitational_force(x2, y2, mass2, x1, y1, mass1, &physics);
+            let tolerance = 1e-5 * action.x_component.hypot(action.y_component);
+            prop_assert!((action.x_component + reaction.x_component).abs() <= tolerance);
+            prop_assert!((action.y_component + reaction.y_component).abs() <= tolerance);
+        }
+
+        #[test]
+        fn only_depends_on_the_displacement(
+            ((x1, y1), (x2, y2)) in pixel_pairs(),
+            (dx, dy) in (0u32..256, 0u32..256),
+            law in force_laws(),
+        ) {
+            let physics = physics(law);
+            let force = gravitational_force(x1, y1, 1.0, x2, y2, 10.0, &physics);
+            let moved = gravitational_force(x1 + dx, y1 + dy, 1.0, x2 + dx, y2 + dy, 10.0, &physics);
+            prop_assert_eq!(force.x_component, moved.x_component);
+            prop_assert_eq!(force.y_component, moved.y_component);
+        }
     }
 }
@@ -0,0 +1,386 @@
+use image::{DynamicImage, GrayImage, Luma};
+
+use crate::config::{AlphaMode, AttractorConfig, Channel, Extractor};
+
+/// Canny hysteresis thresholds, in units of the Sobel gradient magnitude. A hard step from black
+/// to white has a magnitude of about 1000 before blurring.
+const CANNY_HIGH: f32 = 200.0;
+const CANNY_LOW: f32 = 80.0;
+
+/// Turns `img` into a single grayscale channel with the extractor of `config`. Bright pixels of
+/// the result are candidates for attractors, as decided by the threshold and mode of `config`.
+pub fn extract(img: &DynamicImage, config: &AttractorConfig) -> GrayImage {
+    let channel = select_channel(img, config.channel, config.alpha);
+    let threshold = (config.threshold * 255.0) as u8;
+
+    match config.extractor {
+        Extractor::Plain => channel,
+        Extractor::Inverted => map_pixels(channel, |value| 255 - value),
+        Extractor::Otsu => {
+            let threshold = otsu_threshold(&channel);
+            map_pixels(channel, |value| if value > threshold { 255 } else { 0 })
+        }
+        Extractor::Sobel => {
+            let (width, height) = channel.dimensions();
+            let magnitudes = sobel(&channel).0;
+            // A hard edge between black and white is fully bright.
+            GrayImage::from_fn(width, height, |x, y| {
+                Luma([(magnitudes[(x + y * width) as usize] / 4.0).min(255.0) as u8])
+            })
+        }
+        Extractor::Canny => canny(&channel),
+        Extractor::Skeleton => skeleton(&binarize(channel, threshold)),
+        Extractor::Distance => distance_map(&binarize(channel, threshold)),
+    }
+}
+
+fn select_channel(img: &DynamicImage, channel: Channel, alpha_mode: AlphaMode) -> GrayImage {
+    let rgba_channel = |index: usize| {
+        img.to_rgba8()
+            .pixels()
+            .map(|pixel| (pixel.0[index], pixel.0[3]))
+            .unzip()
+    };
+    let (values, alphas): (Vec<u8>, Vec<u8>) = match channel {
+        // `grayscale` rounds down, unlike `to_luma_alpha8`, and is what images always went
+        // through.
+        Channel::Luma => img
+            .grayscale()
+            .to_luma_alpha8()
+            .pixels()
+            .map(|pixel| (pixel.0[0], pixel.0[1]))
+            .unzip(),
+        Channel::Red => rgba_channel(0),
+        Channel::Green => rgba_channel(1),
+        Channel::Blue => rgba_channel(2),
+    };
+
+    let mut channel = GrayImage::from_vec(img.width(), img.height(), values).unwrap();
+    for (pixel, alpha) in channel.pixels_mut().zip(alphas) {
+        pixel.0[0] = match alpha_mode {
+            AlphaMode::Threshold if alpha <= 127 => 0,
+            AlphaMode::Threshold | AlphaMode::Ignore => pixel.0[0],
+            AlphaMode::Mass => (pixel.0[0] as u32 * alpha as u32 / 255) as u8,
+        };
+    }
+    channel
+}
+
+fn map_pixels(mut img: GrayImage, function: impl Fn(u8) -> u8) -> GrayImage {
+    for pixel in img.pixels_mut() {
+        pixel.0[0] = function(pixel.0[0]);
+    }
+    img
+}
+
+/// Pixels above `threshold` become white, the others black.
+fn binarize(img: GrayImage, threshold: u8) -> GrayImage {
+    map_pixels(img, |value| if value > threshold { 255 } else { 0 })
+}
+
+/// The value that best separates the histogram of `img` into a dark and a bright class, by
+/// maximizing the variance between both classes.
+fn otsu_threshold(img: &GrayImage) -> u8 {
+    let mut histogram = [0u64; 256];
+    for pixel in img.pixels() {
+        histogram[pixel.0[0] as usize] += 1;
+    }
+
+    let total = img.pixels().len() as f64;
+    let total_sum: f64 = (0..256)
+        .map(|value| value as f64 * histogram[value] as f64)
+        .sum();
+
+    let mut best = (0, 0.0);
+    let mut dark_count = 0.0;
+    let mut dark_sum = 0.0;
+    for (value, count) in histogram.iter().enumerate() {
+        dark_count += *count as f64;
+        dark_sum += value as f64 * *count as f64;
+        let bright_count = total - dark_count;
+        if dark_count == 0.0 || bright_count == 0.0 {
+            continue;
+        }
+
+        let dark_mean = dark_sum / dark_count;
+        let bright_mean = (total_sum - dark_sum) / bright_count;
+        let variance = dark_count * bright_count * (dark_mean - bright_mean).powi(2);
+        if variance > best.1 {
+            best = (value as u8, variance);
+        }
+    }
+    best.0
+}
+
+/// Reads a pixel, repeating the border for coordinates outside the image.
+fn get_clamped(img: &GrayImage, x: i64, y: i64) -> f32 {
+    let x = x.clamp(0, img.width() as i64 - 1) as u32;
+    let y = y.clamp(0, img.height() as i64 - 1) as u32;
+    img.get_pixel(x, y).0[0] as f32
+}
+
+/// Gradient of every pixel with the Sobel operator, as (magnitudes, directions in radians).
+fn sobel(img: &GrayImage) -> (Vec<f32>, Vec<f32>) {
+    let (width, height) = img.dimensions();
+    let mut magnitudes = Vec::with_capacity((width * height) as usize);
+    let mut directions = Vec::with_capacity((width * height) as usize);
+
+    for y in 0..height as i64 {
+        for x in 0..width as i64 {
+            let at = |dx: i64, dy: i64| get_clamped(img, x + dx, y + dy);
+            let gx =
+                at(1, -1) + 2.0 * at(1, 0) + at(1, 1) - at(-1, -1) - 2.0 * at(-1, 0) - at(-1, 1);
+            let gy =
+                at(-1, 1) + 2.0 * at(0, 1) + at(1, 1) - at(-1, -1) - 2.0 * at(0, -1) - at(1, -1);
+            magnitudes.push((gx * gx + gy * gy).sqrt());
+            directions.push(gy.atan2(gx));
+        }
+    }
+
+    (magnitudes, directions)
+}
+
+/// Smooths the image with a 5x5 binomial kernel, an approximation of a Gaussian blur.
+fn blur(img: &GrayImage) -> GrayImage {
+    const KERNEL: [f32; 5] = [1.0, 4.0, 6.0, 4.0, 1.0];
+    let (width, height) = img.dimensions();
+
+    let horizontal = GrayImage::from_fn(width, height, |x, y| {
+        let sum: f32 = (0..5)
+            .map(|i| KERNEL[i] * get_clamped(img, x as i64 + i as i64 - 2, y as i64))
+            .sum();
+        Luma([(sum / 16.0).round() as u8])
+    });
+    GrayImage::from_fn(width, height, |x, y| {
+        let sum: f32 = (0..5)
+            .map(|i| KERNEL[i] * get_clamped(&horizontal, x as i64, y as i64 + i as i64 - 2))
+            .sum();
+        Luma([(sum / 16.0).round() as u8])
+    })
+}
+
+fn canny(img: &GrayImage) -> GrayImage {
+    let (width, height) = img.dimensions();
+    let (magnitudes, directions) = sobel(&blur(img));
+    let magnitude = |x: i64, y: i64| {
+        if x < 0 || y < 0 || x >= width as i64 || y >= height as i64 {
+            0.0
+        } else {
+            magnitudes[(x + y * width as i64) as usize]
+        }
+    };
+
+    // Keep only the pixels where the gradient is strongest across the edge, so edges are one
+    // pixel wide.
+    let mut thin = vec![0.0; magnitudes.len()];
+    for y in 0..height as i64 {
+        for x in 0..width as i64 {
+            let index = (x + y * width as i64) as usize;
+            let angle = directions[index].to_degrees().rem_euclid(180.0);
+            let (dx, dy) = if !(22.5..157.5).contains(&angle) {
+                (1, 0)
+            } else if angle < 67.5 {
+                (1, 1)
+            } else if angle < 112.5 {
+                (0, 1)
+            } else {
+                (-1, 1)
+            };
+            let current = magnitudes[index];
+            if current >= magnitude(x + dx, y + dy) && current >= magnitude(x - dx, y - dy) {
+                thin[index] = current;
+            }
+        }
+    }
+
+    // Hysteresis: strong edges are kept, weak ones only if they connect to a strong one.
+    let mut edges = GrayImage::new(width, height);
+    let mut stack: Vec<(u32, u32)> = vec![];
+    for y in 0..height {
+        for x in 0..width {
+            if thin[(x + y * width) as usize] >= CANNY_HIGH {
+                edges.put_pixel(x, y, Luma([255]));
+                stack.push((x, y));
+            }
+        }
+    }
+    while let Some((x, y)) = stack.pop() {
+        for (nx, ny) in neighbours(x, y, width, height) {
+            if edges.get_pixel(nx, ny).0[0] == 0 && thin[(nx + ny * width) as usize] >= CANNY_LOW {
+                edges.put_pixel(nx, ny, Luma([255]));
+                stack.push((nx, ny));
+            }
+        }
+    }
+
+    edges
+}
+
+/// The up to eight pixels around (x, y) that lie inside the image.
+fn neighbours(x: u32, y: u32, width: u32, height: u32) -> impl Iterator<Item = (u32, u32)> {
+    (-1i64..=1)
+        .flat_map(|dy| (-1i64..=1).map(move |dx| (dx, dy)))
+        .filter(|&(dx, dy)| dx != 0 || dy != 0)
+        .map(move |(dx, dy)| (x as i64 + dx, y as i64 + dy))
+        .filter(move |&(nx, ny)| nx >= 0 && ny >= 0 && nx < width as i64 && ny < height as i64)
+        .map(|(nx, ny)| (nx as u32, ny as u32))
+}
+
+/// Thins the white shapes of a binary image down to lines one pixel wide, with the Zhang-Suen
+/// algorithm.
+fn skeleton(img: &GrayImage) -> GrayImage {
+    let (width, height) = img.dimensions();
+    let mut skeleton = img.clone();
+    let is_set = |img: &GrayImage, x: i64, y: i64| {
+        x >= 0
+            && y >= 0
+            && x < width as i64
+            && y < height as i64
+            && img.get_pixel(x as u32, y as u32).0[0] != 0
+    };
+
+    loop {
+        let mut changed = false;
+        for step in 0..2 {
+            let mut remove = vec![];
+            for y in 0..height as i64 {
+                for x in 0..width as i64 {
+                    if !is_set(&skeleton, x, y) {
+                        continue;
+                    }
+                    // Neighbours clockwise, starting from the one above.
+                    let p: Vec<bool> = [
+                        (0, -1),
+                        (1, -1),
+                        (1, 0),
+                        (1, 1),
+                        (0, 1),
+                        (-1, 1),
+                        (-1, 0),
+                        (-1, -1),
+                    ]
+                    .iter()
+                    .map(|(dx, dy)| is_set(&skeleton, x + dx, y + dy))
+                    .collect();
+                    let count = p.iter().filter(|&&set| set).count();
+                    let transitions = (0..8).filter(|&i| !p[i] && p[(i + 1) % 8]).count();
+                    let (first, second) = if step == 0 {
+                        (p[0] && p[2] && p[4], p[2] && p[4] && p[6])
+                    } else {
+                        (p[0] && p[2] && p[6], p[0] && p[4] && p[6])
+                    };
+                    if (2..=6).contains(&count) && transitions == 1 && !first && !second {
+                        remove.push((x as u32, y as u32));
+                    }
+                }
+            }
+
+            changed |= !remove.is_empty();
+            for (x, y) in remove {
+                skeleton.put_pixel(x, y, Luma([0]));
+            }
+        }
+
+        if !changed {
+            return skeleton;
+        }
+    }
+}
+
+/// Replaces every white pixel of a binary image by its distance to the nearest black pixel (or
+/// the border of the image), scaled so the largest distance is white.
+fn distance_map(img: &GrayImage) -> GrayImage {
+    // Chamfer distances: 3 for a straight step, 4 for a diagonal one.
+    const FORWARD: [(i64, i64, u32); 4] = [(-1, 0, 3), (-1, -1, 4), (0, -1, 3), (1, -1, 4)];
+    let (width, height) = img.dimensions();
+    let index = |x: i64, y: i64| (x + y * width as i64) as usize;
+    let distance_at = |distances: &[u32], x: i64, y: i64| {
+        if x < 0 || y < 0 || x >= width as i64 || y >= height as i64 {
+            0
+        } else {
+            distances[index(x, y)]
+        }
+    };
+
+    let mut distances: Vec<u32> = img
+        .pixels()
+        .map(|pixel| if pixel.0[0] != 0 { u32::MAX } else { 0 })
+        .collect();
+
+    for y in 0..height as i64 {
+        for x in 0..width as i64 {
+            for (dx, dy, cost) in FORWARD {
+                let candidate = distance_at(&distances, x + dx, y + dy).saturating_add(cost);
+                distances[index(x, y)] = distances[index(x, y)].min(candidate);
+            }
+        }
+    }
+    for y in (0..height as i64).rev() {
+        for x in (0..width as i64).rev() {
+            for (dx, dy, cost) in FORWARD {
+                let candidate = distance_at(&distances, x - dx, y - dy).saturating_add(cost);
+                distances[index(x, y)] = distances[index(x, y)].min(candidate);
+            }
+        }
+    }
+
+    let max = distances.iter().copied().max().unwrap_or(0).max(1);
+    GrayImage::from_fn(width, height, |x, y| {
+        Luma([(distances[index(x as i64, y as i64)] * 255 / max) as u8])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+
+    use super::*;
+
+    /// A light gray pixel, a bit more opaque than not.
+    fn translucent() -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_pixel(1, 1, Rgba([200, 200, 200, 150])))
+    }
+
+    #[test]
+    fn pixels_at_most_half_opaque_are_left_out_by_default() {
+        let config = AttractorConfig::default();
+        let value = extract(&translucent(), &config).get_pixel(0, 0).0[0];
+        assert_eq!(value, 200);
+        assert!(config.weight(value).is_some());
+
+        for alpha in [0, 100, 127] {
+            let img = RgbaImage::from_pixel(1, 1, Rgba([255, 255, 255, alpha]));
+            let value = extract(&DynamicImage::ImageRgba8(img), &config)
+                .get_pixel(0, 0)
+                .0[0];
+            assert_eq!(value, 0);
+            assert!(config.weight(value).is_none());
+        }
+    }
+
+    #[test]
+    fn ignore_keeps_transparent_pixels() {
+        let config = AttractorConfig {
+            alpha: AlphaMode::Ignore,
+            ..AttractorConfig::default()
+        };
+        let img = RgbaImage::from_pixel(1, 1, Rgba([255, 255, 255, 0]));
+        let value = extract(&DynamicImage::ImageRgba8(img), &config)
+            .get_pixel(0, 0)
+            .0[0];
+        assert_eq!(value, 255);
+        assert!(config.weight(value).is_some());
+    }
+
+    #[test]
+    fn mass_lays_the_image_over_black() {
+        let config = AttractorConfig {
+            alpha: AlphaMode::Mass,
+            ..AttractorConfig::default()
+        };
+        let value = extract(&translucent(), &config).get_pixel(0, 0).0[0];
+        // 200 * 150 / 255, below the default threshold of half the brightness.
+        assert_eq!(value, 117);
+        assert!(config.weight(value).is_none());
+    }
+}
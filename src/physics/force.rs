@@ -1,6 +1,6 @@
 use std::ops::{Add, AddAssign, Div, Mul, Sub};
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct Force<T>
 where
     T: Clone,
@@ -67,3 +67,76 @@ impl<T: Clone + Mul<Output = T>> Mul<T> for Force<T> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn force(x: f32, y: f32) -> Force<f32> {
+        Force {
+            x_component: x,
+            y_component: y,
+        }
+    }
+
+    fn forces() -> impl Strategy<Value = Force<f32>> {
+        (-1e3f32..1e3, -1e3f32..1e3).prop_map(|(x, y)| force(x, y))
+    }
+
+    /// Checks that both components agree, up to the rounding of values as large as `scale`.
+    fn assert_close(actual: Force<f32>, expected: Force<f32>, scale: f32) {
+        let tolerance = 1e-5 * scale.max(1.0);
+        assert!(
+            (actual.x_component - expected.x_component).abs() <= tolerance
+                && (actual.y_component - expected.y_component).abs() <= tolerance,
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn operations_act_on_each_component() {
+        assert_close(force(1.0, 2.0) + force(3.0, -5.0), force(4.0, -3.0), 1.0);
+        assert_close(force(1.0, 2.0) - force(3.0, -5.0), force(-2.0, 7.0), 1.0);
+        assert_close(force(1.0, -2.0) * 3.0, force(3.0, -6.0), 1.0);
+        assert_close(force(3.0, -6.0) / 3.0, force(1.0, -2.0), 1.0);
+        assert_close(Force::default(), force(0.0, 0.0), 1.0);
+    }
+
+    proptest! {
+        #[test]
+        fn addition_commutes(a in forces(), b in forces()) {
+            assert_close(a.clone() + b.clone(), b + a, 1e3);
+        }
+
+        #[test]
+        fn add_assign_matches_add(a in forces(), b in forces()) {
+            let mut sum = a.clone();
+            sum += b.clone();
+            assert_close(sum, a + b, 1e3);
+        }
+
+        #[test]
+        fn subtraction_undoes_addition(a in forces(), b in forces()) {
+            assert_close(a.clone() + b.clone() - b, a, 1e3);
+        }
+
+        #[test]
+        fn default_is_neutral(a in forces()) {
+            assert_close(a.clone() + Force::default(), a, 1e3);
+        }
+
+        #[test]
+        fn division_undoes_multiplication(a in forces(), k in 1e-2f32..1e2) {
+            assert_close(a.clone() * k / k, a, 1e3);
+        }
+
+        #[test]
+        fn scaling_distributes(a in forces(), b in forces(), k in -1e2f32..1e2) {
+            assert_close((a.clone() + b.clone()) * k, a * k + b * k, 1e5);
+        }
+    }
+}
@@ -1,20 +1,46 @@
-use super::{
-    engine::{PARTICLE_MASS, TIMESTEP},
-    force::Force,
-};
+use super::force::Force;
+use crate::config::PhysicsConfig;
 
 #[derive(Clone)]
 pub struct Particle {
+    /// Identifies the particle for as long as it lives. Particles added later get higher ids.
+    pub id: u64,
     pub x: f32,
     pub y: f32,
     pub velocity: Force<f32>,
+    /// How visible the particle is, from 0 to 1. Below 1 while it fades in or out.
+    pub opacity: f32,
+    /// How much the opacity changes with each update: positive while the particle fades in,
+    /// negative while it fades out before being removed.
+    pub fade: f32,
+    /// The pixel the particle is pulled to in `assignment` mode, if it has one.
+    pub target: Option<(u32, u32)>,
+}
+
+impl Default for Particle {
+    fn default() -> Self {
+        Self {
+            id: 0,
+            x: 0.0,
+            y: 0.0,
+            velocity: Force::default(),
+            opacity: 1.0,
+            fade: 0.0,
+            target: None,
+        }
+    }
 }
 
 impl Particle {
+    /// Whether the particle is fading out, to be removed.
+    pub fn is_dying(&self) -> bool {
+        self.fade < 0.0
+    }
+
     pub fn get_render_position(&self, max_x: u32, max_y: u32) -> (u32, u32) {
         let render_x = (self.x.round() as u32).clamp(0, max_x);
         let render_y = (self.y.round() as u32).clamp(0, max_y);
-        return (render_x, render_y);
+        (render_x, render_y)
     }
 
     pub fn is_inside(&self, max_x: u32, max_y: u32) -> bool {
@@ -22,7 +48,7 @@ impl Particle {
         let unclamped_y = self.y.round() as i32;
 
         let (render_x, render_y) = self.get_render_position(max_x, max_y);
-        return (unclamped_x == (render_x as i32)) && (unclamped_y == (render_y as i32));
+        (unclamped_x == (render_x as i32)) && (unclamped_y == (render_y as i32))
     }
 
     pub fn is_heading_inside(&self, max_x: i32, max_y: i32) -> bool {
@@ -35,13 +61,26 @@ impl Particle {
         let heading_inside_y = (unclamped_y < 0 && self.velocity.y_component > 0.0)
             || (unclamped_y > max_y && self.velocity.y_component <= 0.0);
 
-        return heading_inside_x && heading_inside_y;
+        heading_inside_x && heading_inside_y
+    }
+
+    /// The pull of the spring to the target in `assignment` mode, damped by the velocity.
+    pub fn spring_force(&self, physics: &PhysicsConfig) -> Force<f32> {
+        let Some((target_x, target_y)) = self.target else {
+            return Force::default();
+        };
+        Force {
+            x_component: physics.spring * (target_x as f32 - self.x)
+                - physics.spring_damping * self.velocity.x_component,
+            y_component: physics.spring * (target_y as f32 - self.y)
+                - physics.spring_damping * self.velocity.y_component,
+        }
     }
 
-    pub fn update_velocity(&mut self, total_force: Force<f32>) {
+    pub fn update_velocity(&mut self, total_force: Force<f32>, physics: &PhysicsConfig) {
         // F = M * A , so the acceleration is
         // A = F / M
-        let acceleration = total_force / PARTICLE_MASS;
+        let acceleration = total_force / physics.particle_mass;
 
         // Now, the new velocity would be:
         // V = V0 + A*dt
@@ -49,16 +88,149 @@ impl Particle {
         // and "dt" is a how much "time" passed between now and the previous time update was called
         // Note: this time is relative to the simulation, and not real-life time
 
-        self.velocity = self.velocity.clone() + acceleration * TIMESTEP;
+        self.velocity = self.velocity.clone() + acceleration * physics.timestep;
+
+        // Drag takes away a fraction of the velocity, and never reverses it.
+        if physics.drag > 0.0 {
+            self.velocity =
+                self.velocity.clone() * (1.0 - physics.drag * physics.timestep).max(0.0);
+        }
     }
 
-    pub fn update_position(&mut self) {
+    pub fn update_position(&mut self, physics: &PhysicsConfig) {
         // X = X0 + V*dt
         // where X0 is the previous position (on the x coordinate)
         // and "dt" is a how much "time" passed between now and the previous time update was called
         // Note: this time is relative to the simulation, and not real-life time
 
-        self.x = self.x + self.velocity.x_component * TIMESTEP;
-        self.y = self.y + self.velocity.y_component * TIMESTEP;
+        self.x += self.velocity.x_component * physics.timestep;
+        self.y += self.velocity.y_component * physics.timestep;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Kinetic energy of the particle plus the energy stored in its spring.
+    fn spring_energy(particle: &Particle, physics: &PhysicsConfig) -> f32 {
+        let (target_x, target_y) = particle.target.unwrap();
+        let (dx, dy) = (particle.x - target_x as f32, particle.y - target_y as f32);
+        let speed = particle
+            .velocity
+            .x_component
+            .hypot(particle.velocity.y_component);
+        0.5 * physics.particle_mass * speed * speed + 0.5 * physics.spring * (dx * dx + dy * dy)
+    }
+
+    /// Steps a particle 20 pixels away from its target, as `Board::update` does in assignment
+    /// mode without a static field, and returns its energy after every step.
+    fn spring_energies(physics: &PhysicsConfig, steps: usize) -> Vec<f32> {
+        let mut particle = Particle {
+            x: 30.0,
+            y: 10.0,
+            target: Some((10, 10)),
+            ..Default::default()
+        };
+        (0..steps)
+            .map(|_| {
+                let force = particle.spring_force(physics);
+                particle.update_velocity(force, physics);
+                particle.update_position(physics);
+                spring_energy(&particle, physics)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn undamped_spring_keeps_its_energy() {
+        // The updates are a semi-implicit Euler integration: the energy of an undamped spring
+        // wobbles slightly within each period, but doesn't drift over many of them.
+        let physics = PhysicsConfig {
+            spring_damping: 0.0,
+            ..PhysicsConfig::default()
+        };
+        let initial = 0.5 * physics.spring * 20.0 * 20.0;
+        let energies = spring_energies(&physics, 5000);
+        for energy in &energies {
+            assert!(
+                (energy - initial).abs() <= 0.05 * initial,
+                "{} vs {}",
+                energy,
+                initial
+            );
+        }
+    }
+
+    #[test]
+    fn damped_spring_loses_its_energy() {
+        let physics = PhysicsConfig::default();
+        let initial = 0.5 * physics.spring * 20.0 * 20.0;
+        let energies = spring_energies(&physics, 500);
+        assert!(energies.iter().all(|&energy| energy <= 1.05 * initial));
+        assert!(energies.last().unwrap() < &(0.01 * initial));
+    }
+
+    #[test]
+    fn drag_slows_particles_down() {
+        let physics = PhysicsConfig {
+            drag: 0.1,
+            ..PhysicsConfig::default()
+        };
+        let mut particle = Particle {
+            velocity: Force {
+                x_component: 3.0,
+                y_component: -4.0,
+            },
+            ..Default::default()
+        };
+        let mut speed = 5.0;
+        for _ in 0..50 {
+            particle.update_velocity(Force::default(), &physics);
+            let new_speed = particle
+                .velocity
+                .x_component
+                .hypot(particle.velocity.y_component);
+            assert!(new_speed < speed);
+            assert!(particle.velocity.x_component > 0.0 && particle.velocity.y_component < 0.0);
+            speed = new_speed;
+        }
+    }
+
+    #[test]
+    fn drag_never_reverses_the_velocity() {
+        let physics = PhysicsConfig {
+            drag: 2.0,
+            ..PhysicsConfig::default()
+        };
+        let mut particle = Particle {
+            velocity: Force {
+                x_component: 3.0,
+                y_component: -4.0,
+            },
+            ..Default::default()
+        };
+        particle.update_velocity(Force::default(), &physics);
+        assert_eq!(particle.velocity.x_component, 0.0);
+        assert_eq!(particle.velocity.y_component, 0.0);
+    }
+
+    #[test]
+    fn free_particles_move_in_straight_lines() {
+        let physics = PhysicsConfig::default();
+        let mut particle = Particle {
+            x: 1.0,
+            y: 2.0,
+            velocity: Force {
+                x_component: 0.5,
+                y_component: 0.25,
+            },
+            ..Default::default()
+        };
+        for _ in 0..8 {
+            particle.update_velocity(Force::default(), &physics);
+            particle.update_position(&physics);
+        }
+        assert_eq!((particle.x, particle.y), (5.0, 4.0));
     }
 }
@@ -0,0 +1,107 @@
+use std::f32::consts::{PI, TAU};
+
+use image::{DynamicImage, GrayImage, Luma};
+
+use crate::{HEIGHT, WIDTH};
+
+/// Animations generated in memory, for testing and demos without any video. Each one loops once
+/// over the length of the sequence.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Procedural {
+    /// A square turning a quarter turn around the center.
+    RotatingShape,
+    /// A ring growing from the center to the edges of the board.
+    ExpandingRing,
+    /// A ball bouncing on the floor while moving between the walls.
+    BouncingBall,
+    /// A Lissajous curve slowly changing its phase.
+    Lissajous,
+}
+
+impl Procedural {
+    /// Draws frame `index` out of `count`, white on black.
+    pub fn render(&self, index: usize, count: usize) -> DynamicImage {
+        // How far into the loop this frame is, from 0 (inclusive) to 1 (exclusive).
+        let time = index as f32 / count.max(1) as f32;
+        let size = WIDTH.min(HEIGHT) as f32;
+        let center = (WIDTH as f32 / 2.0, HEIGHT as f32 / 2.0);
+
+        let image = match self {
+            Procedural::RotatingShape => {
+                let angle = time * PI / 2.0;
+                let half_side = size * 0.25;
+                draw(|x, y| {
+                    // Rotate the pixel into the frame of the square.
+                    let (dx, dy) = (x - center.0, y - center.1);
+                    let (sin, cos) = (-angle).sin_cos();
+                    let (local_x, local_y) = (dx * cos - dy * sin, dx * sin + dy * cos);
+                    f32::max(local_x.abs(), local_y.abs()) - half_side
+                })
+            }
+            Procedural::ExpandingRing => {
+                let radius = time * size * 0.45;
+                let half_thickness = size * 0.03;
+                draw(|x, y| (distance((x, y), center) - radius).abs() - half_thickness)
+            }
+            Procedural::BouncingBall => {
+                let radius = size * 0.1;
+                // Left to right and back, while bouncing three times.
+                let x_range = WIDTH as f32 - 2.0 * radius;
+                let ball_x = radius + x_range * (1.0 - (2.0 * time - 1.0).abs());
+                let height = (HEIGHT as f32 - 2.0 * radius) * 0.8;
+                let ball_y = HEIGHT as f32 - radius - height * (time * 3.0 * PI).sin().abs();
+                draw(|x, y| distance((x, y), (ball_x, ball_y)) - radius)
+            }
+            Procedural::Lissajous => {
+                const SAMPLES: usize = 400;
+                let phase = time * TAU;
+                let amplitude = size * 0.4;
+                let points: Vec<(f32, f32)> = (0..=SAMPLES)
+                    .map(|sample| {
+                        let t = sample as f32 / SAMPLES as f32 * TAU;
+                        (
+                            center.0 + amplitude * (3.0 * t + phase).sin(),
+                            center.1 + amplitude * (2.0 * t).sin(),
+                        )
+                    })
+                    .collect();
+                let half_thickness = size * 0.015;
+                draw(|x, y| {
+                    points
+                        .windows(2)
+                        .map(|segment| segment_distance((x, y), segment[0], segment[1]))
+                        .fold(f32::INFINITY, f32::min)
+                        - half_thickness
+                })
+            }
+        };
+
+        DynamicImage::ImageLuma8(image)
+    }
+}
+
+/// Draws a shape given by its signed distance function: negative inside the shape, positive
+/// outside, in pixels. Edges are anti-aliased over one pixel.
+fn draw(signed_distance: impl Fn(f32, f32) -> f32) -> GrayImage {
+    GrayImage::from_fn(WIDTH, HEIGHT, |x, y| {
+        let coverage = (0.5 - signed_distance(x as f32 + 0.5, y as f32 + 0.5)).clamp(0.0, 1.0);
+        Luma([(coverage * 255.0).round() as u8])
+    })
+}
+
+fn distance(a: (f32, f32), b: (f32, f32)) -> f32 {
+    ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt()
+}
+
+/// Distance from `point` to the segment between `a` and `b`.
+fn segment_distance(point: (f32, f32), a: (f32, f32), b: (f32, f32)) -> f32 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let length_squared = dx * dx + dy * dy;
+    let t = if length_squared == 0.0 {
+        0.0
+    } else {
+        (((point.0 - a.0) * dx + (point.1 - a.1) * dy) / length_squared).clamp(0.0, 1.0)
+    };
+    distance(point, (a.0 + t * dx, a.1 + t * dy))
+}
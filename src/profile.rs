@@ -0,0 +1,288 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Debug,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use physics_apple::{Error, Result};
+use serde::Serialize;
+use tracing::{
+    field::{Field, Visit},
+    span, Subscriber,
+};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+/// The stages timed by the library, in the order of the report. Spans with other names are
+/// listed after them.
+const STAGES: [&str; 5] = ["load", "field", "simulate", "render", "encode"];
+
+/// Collects how long each stage takes from the spans of the library, with how many steps were
+/// simulated and how much memory the program used. Create it with `Profiler::new`, install its
+/// layer with the logging, and print the report once the command is done.
+pub struct Profiler {
+    start: Instant,
+    stats: Arc<Mutex<Stats>>,
+}
+
+/// The `tracing` layer feeding a `Profiler`.
+pub struct ProfileLayer {
+    stats: Arc<Mutex<Stats>>,
+}
+
+#[derive(Default)]
+struct Stats {
+    stages: BTreeMap<&'static str, Stage>,
+    steps: u64,
+    particle_updates: u64,
+    peak_memory: Option<usize>,
+}
+
+#[derive(Clone, Copy, Default, Serialize)]
+struct Stage {
+    calls: u64,
+    seconds: f64,
+}
+
+/// Kept in the extensions of every span until it closes.
+#[derive(Default)]
+struct Timing {
+    entered: Option<Instant>,
+    busy: Duration,
+    /// Time spent in the spans opened inside this one, which is counted for their stage instead.
+    children: Duration,
+    steps: u64,
+    particle_updates: u64,
+}
+
+impl Visit for Timing {
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        match field.name() {
+            "steps" => self.steps = value,
+            "particle_updates" => self.particle_updates = value,
+            _ => (),
+        }
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.record_u64(field, value.max(0) as u64);
+    }
+
+    fn record_debug(&mut self, _field: &Field, _value: &dyn Debug) {}
+}
+
+/// What `--profile-report` prints and saves as JSON.
+#[derive(Serialize)]
+struct Report {
+    wall_seconds: f64,
+    stages: Vec<StageReport>,
+    steps: u64,
+    steps_per_second: Option<f64>,
+    particle_updates: u64,
+    particle_updates_per_second: Option<f64>,
+    peak_memory_bytes: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct StageReport {
+    name: &'static str,
+    #[serde(flatten)]
+    stage: Stage,
+}
+
+impl Profiler {
+    /// Starts the wall clock, and returns the layer to install alongside the logging.
+    pub fn new() -> (Profiler, ProfileLayer) {
+        let stats = Arc::new(Mutex::new(Stats::default()));
+        let profiler = Profiler {
+            start: Instant::now(),
+            stats: stats.clone(),
+        };
+        (profiler, ProfileLayer { stats })
+    }
+
+    /// Prints the summary table to the standard output and, if `json` is given, saves the same
+    /// numbers there.
+    pub fn report(&self, json: Option<&Path>) -> Result<()> {
+        let report = self.collect();
+        println!("{}", report);
+        if let Some(path) = json {
+            let text = serde_json::to_string_pretty(&report).map_err(|err| Error::Encode {
+                path: path.to_path_buf(),
+                message: err.to_string(),
+            })?;
+            std::fs::write(path, text + "\n").map_err(|err| Error::io(path, err))?;
+        }
+        Ok(())
+    }
+
+    fn collect(&self) -> Report {
+        let mut stats = self.stats.lock().unwrap();
+        stats.sample_memory();
+
+        let mut stages: Vec<StageReport> = STAGES
+            .iter()
+            .filter_map(|name| {
+                stats
+                    .stages
+                    .get(name)
+                    .map(|&stage| StageReport { name, stage })
+            })
+            .collect();
+        stages.extend(
+            stats
+                .stages
+                .iter()
+                .filter(|(name, _)| !STAGES.contains(name))
+                .map(|(&name, &stage)| StageReport { name, stage }),
+        );
+
+        let simulated = stats
+            .stages
+            .get("simulate")
+            .map(|stage| stage.seconds)
+            .filter(|&seconds| seconds > 0.0);
+        Report {
+            wall_seconds: self.start.elapsed().as_secs_f64(),
+            stages,
+            steps: stats.steps,
+            steps_per_second: simulated.map(|seconds| stats.steps as f64 / seconds),
+            particle_updates: stats.particle_updates,
+            particle_updates_per_second: simulated
+                .map(|seconds| stats.particle_updates as f64 / seconds),
+            peak_memory_bytes: stats.peak_memory,
+        }
+    }
+}
+
+impl Stats {
+    /// The memory in use is sampled whenever a stage ends, so short peaks in between are missed.
+    fn sample_memory(&mut self) {
+        if let Some(usage) = memory_stats::memory_stats() {
+            self.peak_memory = Some(self.peak_memory.unwrap_or(0).max(usage.physical_mem));
+        }
+    }
+}
+
+impl<S> Layer<S> for ProfileLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let mut timing = Timing::default();
+        attrs.record(&mut timing);
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(timing);
+        }
+    }
+
+    fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(timing) = span.extensions_mut().get_mut::<Timing>() {
+                values.record(timing);
+            }
+        }
+    }
+
+    fn on_enter(&self, id: &span::Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(timing) = span.extensions_mut().get_mut::<Timing>() {
+                timing.entered = Some(Instant::now());
+            }
+        }
+    }
+
+    fn on_exit(&self, id: &span::Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(timing) = span.extensions_mut().get_mut::<Timing>() {
+                if let Some(entered) = timing.entered.take() {
+                    timing.busy += entered.elapsed();
+                }
+            }
+        }
+    }
+
+    fn on_close(&self, id: span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let Some(timing) = span.extensions_mut().remove::<Timing>() else {
+            return;
+        };
+        // A frame loaded while simulating counts as loading, not as simulating.
+        if let Some(parent) = span.parent() {
+            if let Some(parent_timing) = parent.extensions_mut().get_mut::<Timing>() {
+                parent_timing.children += timing.busy;
+            }
+        }
+
+        let mut stats = self.stats.lock().unwrap();
+        let stage = stats.stages.entry(span.name()).or_default();
+        stage.calls += 1;
+        stage.seconds += timing.busy.saturating_sub(timing.children).as_secs_f64();
+        if span.name() == "simulate" {
+            stats.steps += timing.steps;
+            stats.particle_updates += timing.particle_updates;
+        }
+        stats.sample_memory();
+    }
+}
+
+impl std::fmt::Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let total: f64 = self.stages.iter().map(|stage| stage.stage.seconds).sum();
+        writeln!(
+            f,
+            "{:<10} {:>8} {:>12} {:>12} {:>7}",
+            "stage", "calls", "total", "mean", "share"
+        )?;
+        for StageReport { name, stage } in &self.stages {
+            writeln!(
+                f,
+                "{:<10} {:>8} {:>12} {:>12} {:>6.1}%",
+                name,
+                stage.calls,
+                Seconds(stage.seconds),
+                Seconds(stage.seconds / stage.calls.max(1) as f64),
+                if total > 0.0 {
+                    stage.seconds / total * 100.0
+                } else {
+                    0.0
+                }
+            )?;
+        }
+        writeln!(f, "wall time: {}", Seconds(self.wall_seconds))?;
+        if let Some(rate) = self.steps_per_second {
+            writeln!(f, "steps: {} ({:.1} steps/s)", self.steps, rate)?;
+        }
+        if let Some(rate) = self.particle_updates_per_second {
+            writeln!(
+                f,
+                "particle updates: {} ({:.0} particles/s)",
+                self.particle_updates, rate
+            )?;
+        }
+        match self.peak_memory_bytes {
+            Some(bytes) => write!(f, "peak memory: {:.1} MiB", bytes as f64 / 1048576.0),
+            None => write!(f, "peak memory: unknown"),
+        }
+    }
+}
+
+/// Formats a duration in seconds with a readable unit.
+struct Seconds(f64);
+
+impl std::fmt::Display for Seconds {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = if self.0 >= 1.0 {
+            format!("{:.2} s", self.0)
+        } else if self.0 >= 1e-3 {
+            format!("{:.2} ms", self.0 * 1e3)
+        } else {
+            format!("{:.1} µs", self.0 * 1e6)
+        };
+        // Padding is applied to the whole text.
+        f.pad(&text)
+    }
+}
@@ -0,0 +1,96 @@
+use std::path::Path;
+
+use tracing::debug_span;
+
+use crate::{
+    config::Config,
+    error::{Error, Result},
+    physics::board::Board,
+    schedule::Playback,
+};
+
+/// Allocates a frame buffer large enough to hold `board` rendered at the configured scale.
+pub fn render_buffer(board: &Board, config: &Config) -> Vec<u8> {
+    let (width, height) = render_size(board, config);
+    vec![0u8; (width * height * 4) as usize]
+}
+
+/// The size in pixels of `board` rendered at the configured scale.
+pub fn render_size(board: &Board, config: &Config) -> (u32, u32) {
+    let render_scale = config.render.render_scale;
+    (board.width * render_scale, board.heigth * render_scale)
+}
+
+/// Draws the particles of `board` at the configured scale into `buffer`, as RGBA pixels. The
+/// buffer must come from `render_buffer`, and is drawn over as the render style requires (e.g.
+/// the `trails` style fades what was drawn before).
+pub fn draw(board: &Board, buffer: &mut [u8], config: &Config) {
+    let _span = debug_span!("render", particles = board.particles.len()).entered();
+    let (width, height) = render_size(board, config);
+    if config.render.render_scale == 1 {
+        board.draw_particles(buffer, &config.render);
+    } else {
+        board.draw_particles_upscaled(buffer, width, height, config.splat_radius(), &config.render);
+    }
+}
+
+/// Draws the particles of `board` at the configured scale and saves them as a PNG.
+pub fn save_render(board: &Board, buffer: &mut [u8], config: &Config, path: &Path) -> Result<()> {
+    draw(board, buffer, config);
+    let (width, height) = render_size(board, config);
+    let _span = debug_span!("encode", path = %path.display()).entered();
+    image::save_buffer(path, buffer, width, height, image::ColorType::Rgba8).map_err(
+        |err| match err {
+            image::ImageError::IoError(source) => Error::io(path, source),
+            err => Error::Encode {
+                path: path.to_path_buf(),
+                message: err.to_string(),
+            },
+        },
+    )
+}
+
+/// Plays the sequence of `playback` from output frame `first_frame` to the end, and saves every
+/// output frame into `directory` as `render.<frame>.png`. Frames are numbered from 1, with as
+/// many leading zeros as the last one needs. Fails as soon as a frame cannot be simulated or
+/// saved.
+///
+/// # Arguments
+/// - playback: The playback of the sequence, at output frame `first_frame`.
+/// - board, buffer: The board and its render buffer, from `render_buffer`.
+/// - config: The configuration of the run.
+/// - directory: Where to save the frames. It must exist.
+/// - first_frame: The first output frame to render, counted from 0.
+/// - on_render: Called with every output frame once it is saved, and the board it shows.
+/// - on_advance: Called once the board is simulated up to the next output frame, with that frame.
+#[allow(clippy::too_many_arguments)]
+pub fn render_sequence<F1, F2>(
+    playback: &mut Playback,
+    board: &mut Board,
+    buffer: &mut [u8],
+    config: &Config,
+    directory: &Path,
+    first_frame: u64,
+    mut on_render: F1,
+    mut on_advance: F2,
+) -> Result<()>
+where
+    F1: FnMut(u64, &Board),
+    F2: FnMut(u64, &Playback, &Board, &[u8]),
+{
+    let output_frames = playback.output_frames();
+    let width = output_frames.to_string().len();
+    for output_frame in first_frame..output_frames {
+        let path = directory.join(format!(
+            "render.{:0>width$}.png",
+            output_frame + 1,
+            width = width
+        ));
+        save_render(board, buffer, config, &path)?;
+        on_render(output_frame, board);
+
+        playback.advance_to_output(board, output_frame + 1)?;
+        on_advance(output_frame + 1, playback, board, buffer);
+    }
+    Ok(())
+}
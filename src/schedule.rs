@@ -0,0 +1,186 @@
+use tracing::{debug_span, info};
+
+use crate::{
+    config::{
+        AttractorConfig, Config, CutConfig, CutPolicy, FrameRate, Interpolation, PopulationConfig,
+        SequenceConfig, SpawnConfig,
+    },
+    error::Result,
+    frames::Frame,
+    physics::{self, board::Board},
+};
+
+/// When things happen in a sequence, counted in physics updates ("steps") from the start. Input
+/// frames, output frames and steps each have their own rate, and every conversion between them
+/// is done on exact fractions rounded down, so that nothing drifts however long the video is.
+///
+/// Input frame `i` starts at `i / input_fps` seconds and output frame `o` is shown at
+/// `o / output_fps` seconds, like the frames of the original video and its audio track.
+#[derive(Clone, Copy, Debug)]
+pub struct Schedule {
+    input_fps: FrameRate,
+    output_fps: FrameRate,
+    steps_per_second: u64,
+}
+
+impl Schedule {
+    pub fn new(sequence: &SequenceConfig) -> Self {
+        Self {
+            input_fps: sequence.input_fps,
+            output_fps: sequence.output_fps,
+            steps_per_second: sequence.steps_per_second as u64,
+        }
+    }
+
+    /// How many updates have run when output frame `frame` is rendered.
+    pub fn steps_before_output(&self, frame: u64) -> u64 {
+        steps_before(frame, self.output_fps, self.steps_per_second)
+    }
+
+    /// How many updates run before the field of input frame `frame` is used.
+    pub fn steps_before_input(&self, frame: u64) -> u64 {
+        steps_before(frame, self.input_fps, self.steps_per_second)
+    }
+
+    /// The input frame whose field is used by update `step` (counting from 0).
+    pub fn input_at_step(&self, step: u64) -> u64 {
+        // The last frame `i` with `i * steps_per_second / input_fps <= step`, which is the last
+        // with `i * steps_per_second * denominator < (step + 1) * numerator`.
+        let numerator = (step as u128 + 1) * self.input_fps.numerator as u128;
+        let denominator = self.steps_per_second as u128 * self.input_fps.denominator as u128;
+        (numerator.div_ceil(denominator) - 1) as u64
+    }
+
+    /// How many output frames cover `input_frames` input frames, so that the output lasts exactly
+    /// as long as the input (and its audio).
+    pub fn output_frames(&self, input_frames: u64) -> u64 {
+        let numerator = input_frames as u128
+            * self.output_fps.numerator as u128
+            * self.input_fps.denominator as u128;
+        let denominator = self.output_fps.denominator as u128 * self.input_fps.numerator as u128;
+        numerator.div_ceil(denominator) as u64
+    }
+}
+
+/// Number of steps that run in the first `frame` frames at `fps`, rounded down.
+fn steps_before(frame: u64, fps: FrameRate, steps_per_second: u64) -> u64 {
+    let steps = frame as u128 * steps_per_second as u128 * fps.denominator as u128;
+    (steps / fps.numerator as u128) as u64
+}
+
+/// Plays a sequence of frames on a board: runs the updates due by each output frame and switches
+/// to the next input frame at the update where it starts.
+pub struct Playback {
+    schedule: Schedule,
+    frames: Vec<Frame>,
+    attractors: AttractorConfig,
+    interpolation: Interpolation,
+    cuts: CutConfig,
+    spawn: SpawnConfig,
+    population: PopulationConfig,
+    /// Output frames rendered after the last input frame.
+    end_frames: u64,
+    /// Updates run so far.
+    step: u64,
+    /// The input frame the field currently comes from.
+    input: usize,
+}
+
+impl Playback {
+    /// Starts playing `frames` on a board whose field already comes from the first one.
+    pub fn new(frames: Vec<Frame>, config: &Config) -> Self {
+        Self {
+            schedule: Schedule::new(&config.sequence),
+            frames,
+            attractors: config.attractors,
+            interpolation: config.sequence.interpolation,
+            cuts: config.cuts.clone(),
+            spawn: config.spawn.clone(),
+            population: config.population.clone(),
+            end_frames: config.sequence.end_frames as u64,
+            step: 0,
+            input: 0,
+        }
+    }
+
+    pub fn schedule(&self) -> &Schedule {
+        &self.schedule
+    }
+
+    /// Number of output frames in the whole sequence, including the end frames.
+    pub fn output_frames(&self) -> u64 {
+        self.schedule.output_frames(self.frames.len() as u64) + self.end_frames
+    }
+
+    /// How many updates have run, and which input frame the field comes from.
+    pub fn position(&self) -> (u64, usize) {
+        (self.step, self.input)
+    }
+
+    /// Continues from a `position` saved earlier, on a board restored from the same moment.
+    pub fn resume_at(&mut self, step: u64, input: usize) {
+        self.step = step;
+        self.input = input;
+    }
+
+    /// Runs every update due before output frame `frame` is rendered. Fails if an input frame
+    /// cannot be loaded or its field is corrupt.
+    pub fn advance_to_output(&mut self, board: &mut Board, frame: u64) -> Result<()> {
+        let target = self.schedule.steps_before_output(frame);
+        // Population control changes the number of particles between updates, so they are
+        // counted as the updates run.
+        let span = debug_span!(
+            "simulate",
+            frame,
+            steps = target - self.step,
+            particle_updates = tracing::field::Empty
+        )
+        .entered();
+        let mut particle_updates = 0;
+        while self.step < target {
+            // Input frames shorter than a single update are skipped. After the last one, its
+            // field stays.
+            let input =
+                (self.schedule.input_at_step(self.step) as usize).min(self.frames.len() - 1);
+            if input != self.input {
+                self.switch_frame(board, input)?;
+            }
+            if self.population.control {
+                board.control_population(&self.population);
+            }
+            board.update();
+            particle_updates += board.particles.len() as u64;
+            self.step += 1;
+        }
+        span.record("particle_updates", particle_updates);
+        Ok(())
+    }
+
+    /// Moves on to input frame `input`. With interpolation, the field then blends from the
+    /// previous frame over all the updates `input` is shown for. If cuts are detected and
+    /// `input` starts a new shot, the field switches at once and the particles are rearranged
+    /// instead.
+    fn switch_frame(&mut self, board: &mut Board, input: usize) -> Result<()> {
+        let frame = &self.frames[input];
+        let blend_steps = self.schedule.steps_before_input(input as u64 + 1) - self.step;
+
+        let previous = board.field();
+        let previous_attractors = std::mem::take(&mut board.attractors);
+        physics::update_static_field(frame, board, frame.load()?, &self.attractors, false)?;
+
+        // Comparing masks takes a while on large frames, only do it when cuts matter.
+        let overlap = (self.cuts.policy != CutPolicy::None)
+            .then(|| physics::mask_overlap(&previous_attractors, &board.attractors));
+        let interpolation = match overlap {
+            Some(overlap) if overlap < self.cuts.threshold => {
+                info!(%frame, overlap, policy = ?self.cuts.policy, "Cut detected");
+                board.redistribute_particles(&self.cuts, &self.spawn, &previous_attractors);
+                Interpolation::None
+            }
+            _ => self.interpolation,
+        };
+        board.start_field_blend(previous, blend_steps as u32, interpolation);
+        self.input = input;
+        Ok(())
+    }
+}
@@ -1,8 +1,9 @@
-extern "C" __constant__ float G = 1.0 / 1000.0;
-
-extern "C" __global__ void gravity(const float mass_product,
+extern "C" __global__ void gravity(const float mass1,
+                                   const float g,
+                                   const int falloff_exponent,
                                    const int* x2,
                                    const int* y2,
+                                   const float* mass2,
                                    const int attractors,
                                    float* out_x,
                                    float* out_y,
@@ -25,11 +26,16 @@ extern "C" __global__ void gravity(const float mass_product,
         const float radius_squared = rx * rx + ry * ry;
 
         const float inv_radius_squared = (radius_squared != 0.0) ? 1.0 / radius_squared : 0.0;
+        // cos_alpha and sin_alpha are already divided by r once more, see engine.rs
+        const float falloff = (falloff_exponent == 3)   ? radius_squared
+                              : (falloff_exponent == 2) ? sqrtf(radius_squared)
+                                                        : 1.0;
+        const float inv_falloff = (radius_squared != 0.0) ? 1.0 / falloff : 0.0;
         const float cos_alpha = rx * inv_radius_squared;
         const float sin_alpha = ry * inv_radius_squared;
 
-        result_x += cos_alpha * G * mass_product * inv_radius_squared;
-        result_y += sin_alpha * G * mass_product * inv_radius_squared;
+        result_x += cos_alpha * g * mass1 * mass2[i] * inv_falloff;
+        result_y += sin_alpha * g * mass1 * mass2[i] * inv_falloff;
     }
 
     out_x[index] = result_x;
@@ -0,0 +1,129 @@
+use std::path::Path;
+
+use ab_glyph::{point, Font, FontRef, PxScale, ScaleFont};
+use image::{DynamicImage, GrayImage, RgbaImage};
+use resvg::{tiny_skia, usvg};
+
+use crate::{
+    error::{Error, Result},
+    HEIGHT, WIDTH,
+};
+
+/// Font used by `simulate-text`: DejaVu Sans Bold, see `fonts/LICENSE-DejaVu.txt`.
+const FONT: &[u8] = include_bytes!("fonts/DejaVuSans-Bold.ttf");
+
+/// Part of the board left empty around text and vector shapes, on every side.
+const MARGIN: f32 = 0.05;
+
+/// Draws `text` in white on black, as large as fits on the board. Lines are separated by `\n`.
+pub fn render_text(text: &str) -> DynamicImage {
+    let font = FontRef::try_from_slice(FONT).expect("The bundled font is valid");
+
+    // Measure at an arbitrary size first, then scale to fit.
+    let (width, height) = measure_text(&font, PxScale::from(100.0), text);
+    let available_width = WIDTH as f32 * (1.0 - 2.0 * MARGIN);
+    let available_height = HEIGHT as f32 * (1.0 - 2.0 * MARGIN);
+    let fit = f32::min(
+        available_width / width.max(1.0),
+        available_height / height.max(1.0),
+    );
+    let scale = PxScale::from(100.0 * fit);
+    let (_, height) = measure_text(&font, scale, text);
+
+    let scaled = font.as_scaled(scale);
+    let line_height = scaled.height() + scaled.line_gap();
+    let mut canvas = GrayImage::new(WIDTH, HEIGHT);
+    let top = (HEIGHT as f32 - height) / 2.0;
+
+    for (line_index, line) in text.lines().enumerate() {
+        let mut x = (WIDTH as f32 - line_width(&font, scale, line)) / 2.0;
+        let baseline = top + scaled.ascent() + line_index as f32 * line_height;
+        let mut previous = None;
+
+        for character in line.chars() {
+            let id = font.glyph_id(character);
+            if let Some(previous) = previous {
+                x += scaled.kern(previous, id);
+            }
+            previous = Some(id);
+
+            let glyph = id.with_scale_and_position(scale, point(x, baseline));
+            x += scaled.h_advance(id);
+            let Some(outline) = font.outline_glyph(glyph) else {
+                continue;
+            };
+
+            let bounds = outline.px_bounds();
+            outline.draw(|glyph_x, glyph_y, coverage| {
+                let x = bounds.min.x as i64 + glyph_x as i64;
+                let y = bounds.min.y as i64 + glyph_y as i64;
+                if x < 0 || y < 0 || x >= WIDTH as i64 || y >= HEIGHT as i64 {
+                    return;
+                }
+                let pixel = canvas.get_pixel_mut(x as u32, y as u32);
+                let value = (coverage.clamp(0.0, 1.0) * 255.0) as u8;
+                pixel.0[0] = pixel.0[0].max(value);
+            });
+        }
+    }
+
+    DynamicImage::ImageLuma8(canvas)
+}
+
+/// Width of a single line of text, including kerning.
+fn line_width(font: &FontRef, scale: PxScale, line: &str) -> f32 {
+    let scaled = font.as_scaled(scale);
+    let mut width = 0.0;
+    let mut previous = None;
+    for character in line.chars() {
+        let id = font.glyph_id(character);
+        if let Some(previous) = previous {
+            width += scaled.kern(previous, id);
+        }
+        width += scaled.h_advance(id);
+        previous = Some(id);
+    }
+    width
+}
+
+/// Size of the block of text: the width of its widest line, and the height of all lines.
+fn measure_text(font: &FontRef, scale: PxScale, text: &str) -> (f32, f32) {
+    let scaled = font.as_scaled(scale);
+    let lines = text.lines().count().max(1) as f32;
+    let width = text
+        .lines()
+        .map(|line| line_width(font, scale, line))
+        .fold(0.0, f32::max);
+    let height = lines * scaled.height() + (lines - 1.0) * scaled.line_gap();
+    (width, height)
+}
+
+/// Rasterises the SVG file at `path`, scaled to fit on the board and centered. Whatever is drawn
+/// keeps its colours, on a black background (like text), so that anti-aliased edges are dimmed
+/// whatever `attractors.alpha` says.
+pub fn render_svg(path: &Path) -> Result<DynamicImage> {
+    let data = std::fs::read(path).map_err(|err| Error::io(path, err))?;
+    let tree = usvg::Tree::from_data(&data, &usvg::Options::default())
+        .map_err(|err| Error::decode(path, err))?;
+
+    let size = tree.size();
+    let fit = f32::min(
+        WIDTH as f32 * (1.0 - 2.0 * MARGIN) / size.width(),
+        HEIGHT as f32 * (1.0 - 2.0 * MARGIN) / size.height(),
+    );
+    let transform = tiny_skia::Transform::from_scale(fit, fit).post_translate(
+        (WIDTH as f32 - size.width() * fit) / 2.0,
+        (HEIGHT as f32 - size.height() * fit) / 2.0,
+    );
+
+    let mut pixmap = tiny_skia::Pixmap::new(WIDTH, HEIGHT)
+        .ok_or_else(|| Error::backend("SVG rendering", "the board has no pixels"))?;
+    resvg::render(&tree, transform, &mut pixmap.as_mut());
+
+    let image = RgbaImage::from_fn(WIDTH, HEIGHT, |x, y| {
+        // The pixmap is premultiplied, i.e. already laid over black.
+        let color = pixmap.pixel(x, y).unwrap();
+        image::Rgba([color.red(), color.green(), color.blue(), 0xff])
+    });
+    Ok(DynamicImage::ImageRgba8(image))
+}
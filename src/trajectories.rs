@@ -0,0 +1,439 @@
+use byteorder::{LittleEndian, WriteBytesExt};
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::Serialize;
+
+use crate::{
+    config::FrameRate,
+    error::{Error, Result},
+    physics::board::Board,
+};
+
+/// Start of binary trajectory files, followed by the format version.
+const TRAJECTORY_MAGIC: &[u8; 8] = b"PATRAJ\0\0";
+const TRAJECTORY_VERSION: u32 = 1;
+
+/// How exported trajectories are written. Positions are in board pixels, velocities in pixels per
+/// unit of simulation time.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum TrajectoryFormat {
+    /// One line per particle and frame: `frame,time,id,x,y,vx,vy,opacity`.
+    Csv,
+    /// One JSON object per frame: `{"frame", "time", "particles": [{"id", "x", "y", "vx", "vy",
+    /// "opacity"}, ...]}`.
+    Jsonl,
+    /// Little-endian binary. A header (`PATRAJ\0\0`, u32 version, u32 width, u32 height, u32 fps
+    /// numerator, u32 fps denominator), then for each frame: u64 frame, f64 time, u64 particle
+    /// count, and per particle u64 id followed by f32 x, y, vx, vy and opacity.
+    Binary,
+}
+
+impl TrajectoryFormat {
+    /// The format matching the extension of `path`: `.csv`, `.jsonl` (or `.json`), and binary
+    /// for anything else.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("csv") => TrajectoryFormat::Csv,
+            Some("jsonl" | "json") => TrajectoryFormat::Jsonl,
+            _ => TrajectoryFormat::Binary,
+        }
+    }
+}
+
+/// A frame of the JSON Lines format.
+#[derive(Serialize)]
+struct JsonFrame {
+    frame: u64,
+    time: f64,
+    particles: Vec<JsonParticle>,
+}
+
+#[derive(Serialize)]
+struct JsonParticle {
+    id: u64,
+    x: f32,
+    y: f32,
+    vx: f32,
+    vy: f32,
+    opacity: f32,
+}
+
+/// Writes the position and velocity of the particles of a sequence, frame after frame.
+pub struct TrajectoryWriter {
+    path: PathBuf,
+    format: TrajectoryFormat,
+    writer: BufWriter<File>,
+    /// Only every `stride`-th output frame is written.
+    stride: u64,
+    /// Fraction of the particles written.
+    sample: f64,
+}
+
+impl TrajectoryWriter {
+    /// Creates the file at `path` and writes the header of `format`.
+    ///
+    /// # Arguments
+    /// - path: Where to write the trajectories.
+    /// - format: The format to write in.
+    /// - stride: Only write every `stride`-th output frame, starting with the first.
+    /// - sample: Only write this fraction (0 to 1) of the particles. The same particles are
+    ///   picked in every frame and on every run, by their id.
+    /// - board: The board that will be recorded.
+    /// - fps: The frame rate of the output frames.
+    pub fn create(
+        path: &Path,
+        format: TrajectoryFormat,
+        stride: u64,
+        sample: f64,
+        board: &Board,
+        fps: FrameRate,
+    ) -> Result<Self> {
+        let (Ok(numerator), Ok(denominator)) =
+            (u32::try_from(fps.numerator), u32::try_from(fps.denominator))
+        else {
+            return Err(Error::Encode {
+                path: path.to_path_buf(),
+                message: format!("the frame rate {} does not fit in the header", fps),
+            });
+        };
+
+        let write_header = || -> io::Result<BufWriter<File>> {
+            let mut writer = BufWriter::new(File::create(path)?);
+            match format {
+                TrajectoryFormat::Csv => writeln!(writer, "frame,time,id,x,y,vx,vy,opacity")?,
+                TrajectoryFormat::Jsonl => {}
+                TrajectoryFormat::Binary => {
+                    writer.write_all(TRAJECTORY_MAGIC)?;
+                    writer.write_u32::<LittleEndian>(TRAJECTORY_VERSION)?;
+                    writer.write_u32::<LittleEndian>(board.width)?;
+                    writer.write_u32::<LittleEndian>(board.heigth)?;
+                    writer.write_u32::<LittleEndian>(numerator)?;
+                    writer.write_u32::<LittleEndian>(denominator)?;
+                }
+            }
+            Ok(writer)
+        };
+        let writer = write_header().map_err(|err| Error::io(path, err))?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            format,
+            writer,
+            stride,
+            sample,
+        })
+    }
+
+    /// Writes the particles of `board` as they are in output frame `output_frame` (counted from
+    /// 0, saved as file number `output_frame + 1`), shown at `time`. Frames left out by the
+    /// stride are skipped.
+    pub fn record(&mut self, output_frame: u64, time: Duration, board: &Board) -> Result<()> {
+        if !output_frame.is_multiple_of(self.stride) {
+            return Ok(());
+        }
+        self.write_frame(output_frame + 1, time, board)
+            .map_err(|err| Error::io(&self.path, err))
+    }
+
+    fn write_frame(&mut self, frame: u64, time: Duration, board: &Board) -> io::Result<()> {
+        let time = time.as_secs_f64();
+        let particles: Vec<JsonParticle> = board
+            .particles
+            .iter()
+            .map(|particle| {
+                let particle = particle.borrow();
+                JsonParticle {
+                    id: particle.id,
+                    x: particle.x,
+                    y: particle.y,
+                    vx: particle.velocity.x_component,
+                    vy: particle.velocity.y_component,
+                    opacity: particle.opacity,
+                }
+            })
+            .filter(|particle| is_sampled(particle.id, self.sample))
+            .collect();
+
+        match self.format {
+            TrajectoryFormat::Csv => {
+                for particle in &particles {
+                    writeln!(
+                        self.writer,
+                        "{},{},{},{},{},{},{},{}",
+                        frame,
+                        time,
+                        particle.id,
+                        particle.x,
+                        particle.y,
+                        particle.vx,
+                        particle.vy,
+                        particle.opacity
+                    )?;
+                }
+            }
+            TrajectoryFormat::Jsonl => {
+                let json_frame = JsonFrame {
+                    frame,
+                    time,
+                    particles,
+                };
+                serde_json::to_writer(&mut self.writer, &json_frame)?;
+                writeln!(self.writer)?;
+            }
+            TrajectoryFormat::Binary => {
+                self.writer.write_u64::<LittleEndian>(frame)?;
+                self.writer.write_f64::<LittleEndian>(time)?;
+                self.writer
+                    .write_u64::<LittleEndian>(particles.len() as u64)?;
+                for particle in &particles {
+                    self.writer.write_u64::<LittleEndian>(particle.id)?;
+                    for value in [
+                        particle.x,
+                        particle.y,
+                        particle.vx,
+                        particle.vy,
+                        particle.opacity,
+                    ] {
+                        self.writer.write_f32::<LittleEndian>(value)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes out everything still buffered.
+    pub fn finish(mut self) -> Result<()> {
+        self.writer
+            .flush()
+            .map_err(|err| Error::io(&self.path, err))
+    }
+}
+
+/// Whether the particle with `id` is part of a sample of `fraction` of all particles. Ids are
+/// hashed (with SplitMix64), so the sample is spread evenly over old and new particles.
+fn is_sampled(id: u64, fraction: f64) -> bool {
+    if fraction >= 1.0 {
+        return true;
+    }
+    let mut hash = id.wrapping_add(0x9e3779b97f4a7c15);
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d049bb133111eb);
+    hash ^= hash >> 31;
+    ((hash >> 11) as f64 / (1u64 << 53) as f64) < fraction
+}
+
+#[cfg(test)]
+mod tests {
+    use byteorder::ReadBytesExt;
+    use std::io::{BufRead, BufReader, Read};
+
+    use super::*;
+    use crate::{config::PhysicsConfig, physics::Attractor};
+
+    /// A frame as read back: its number, time, and (id, [x, y, vx, vy, opacity]) per particle.
+    type Frame = (u64, f64, Vec<(u64, [f32; 5])>);
+
+    /// A board whose particles move towards an attractor, so that every frame differs.
+    fn board() -> Board {
+        let physics = PhysicsConfig::default();
+        let mut board = Board::new(16, 16, physics);
+        board.set_attractors(vec![Attractor {
+            x: 8,
+            y: 8,
+            mass: physics.attractor_mass,
+        }]);
+        board.generate_static_field();
+        board.seed(1);
+        board.random_particles(40);
+        board
+    }
+
+    fn snapshot(board: &Board, frame: u64, time: Duration) -> Frame {
+        let particles = board
+            .particles
+            .iter()
+            .map(|particle| {
+                let particle = particle.borrow();
+                let values = [
+                    particle.x,
+                    particle.y,
+                    particle.velocity.x_component,
+                    particle.velocity.y_component,
+                    particle.opacity,
+                ];
+                (particle.id, values)
+            })
+            .collect();
+        (frame, time.as_secs_f64(), particles)
+    }
+
+    /// Records 6 output frames at 4 fps, and returns the frames as they were on the board. The
+    /// last particle is removed after the third frame.
+    fn record(path: &Path, format: TrajectoryFormat, stride: u64, sample: f64) -> Vec<Frame> {
+        let mut board = board();
+        let fps = FrameRate::from(4);
+        let mut writer =
+            TrajectoryWriter::create(path, format, stride, sample, &board, fps).unwrap();
+        let mut expected = vec![];
+        for output_frame in 0..6 {
+            let time = fps.time_of(output_frame);
+            writer.record(output_frame, time, &board).unwrap();
+            expected.push(snapshot(&board, output_frame + 1, time));
+            for _ in 0..10 {
+                board.update();
+            }
+            if output_frame == 2 {
+                board.particles.pop();
+            }
+        }
+        writer.finish().unwrap();
+        expected
+    }
+
+    fn read_csv(path: &Path) -> Vec<Frame> {
+        let mut lines = BufReader::new(File::open(path).unwrap()).lines();
+        assert_eq!(
+            lines.next().unwrap().unwrap(),
+            "frame,time,id,x,y,vx,vy,opacity"
+        );
+        let mut frames: Vec<Frame> = vec![];
+        for line in lines {
+            let line = line.unwrap();
+            let fields: Vec<&str> = line.split(',').collect();
+            let frame: u64 = fields[0].parse().unwrap();
+            let time: f64 = fields[1].parse().unwrap();
+            let values: Vec<f32> = fields[3..]
+                .iter()
+                .map(|value| value.parse().unwrap())
+                .collect();
+            let particle = (fields[2].parse().unwrap(), values.try_into().unwrap());
+            match frames.last_mut() {
+                Some(last) if last.0 == frame => last.2.push(particle),
+                _ => frames.push((frame, time, vec![particle])),
+            }
+        }
+        frames
+    }
+
+    fn read_jsonl(path: &Path) -> Vec<Frame> {
+        BufReader::new(File::open(path).unwrap())
+            .lines()
+            .map(|line| {
+                let json: serde_json::Value = serde_json::from_str(&line.unwrap()).unwrap();
+                let particles = json["particles"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|particle| {
+                        let values = ["x", "y", "vx", "vy", "opacity"]
+                            .map(|key| particle[key].as_f64().unwrap() as f32);
+                        (particle["id"].as_u64().unwrap(), values)
+                    })
+                    .collect();
+                (
+                    json["frame"].as_u64().unwrap(),
+                    json["time"].as_f64().unwrap(),
+                    particles,
+                )
+            })
+            .collect()
+    }
+
+    fn read_binary(path: &Path) -> Vec<Frame> {
+        let mut reader = BufReader::new(File::open(path).unwrap());
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic).unwrap();
+        assert_eq!(&magic, TRAJECTORY_MAGIC);
+        let mut header = [0u32; 5];
+        reader.read_u32_into::<LittleEndian>(&mut header).unwrap();
+        assert_eq!(header, [TRAJECTORY_VERSION, 16, 16, 4, 1]);
+
+        let mut frames = vec![];
+        while let Ok(frame) = reader.read_u64::<LittleEndian>() {
+            let time = reader.read_f64::<LittleEndian>().unwrap();
+            let count = reader.read_u64::<LittleEndian>().unwrap();
+            let particles = (0..count)
+                .map(|_| {
+                    let id = reader.read_u64::<LittleEndian>().unwrap();
+                    let mut values = [0f32; 5];
+                    reader.read_f32_into::<LittleEndian>(&mut values).unwrap();
+                    (id, values)
+                })
+                .collect();
+            frames.push((frame, time, particles));
+        }
+        frames
+    }
+
+    #[test]
+    fn every_format_reads_back() {
+        let directory = tempfile::tempdir().unwrap();
+        for (format, read) in [
+            (TrajectoryFormat::Csv, read_csv as fn(&Path) -> Vec<Frame>),
+            (TrajectoryFormat::Jsonl, read_jsonl),
+            (TrajectoryFormat::Binary, read_binary),
+        ] {
+            let path = directory.path().join(format!("{:?}", format));
+            let expected = record(&path, format, 1, 1.0);
+            assert_eq!(read(&path), expected, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn stride_skips_frames() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("trajectories.csv");
+        let expected = record(&path, TrajectoryFormat::Csv, 2, 1.0);
+        let frames = read_csv(&path);
+        let numbers: Vec<u64> = frames.iter().map(|frame| frame.0).collect();
+        assert_eq!(numbers, [1, 3, 5]);
+        assert_eq!(
+            frames,
+            [&expected[0], &expected[2], &expected[4]].map(Clone::clone)
+        );
+    }
+
+    #[test]
+    fn ids_stay_the_same_across_frames() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("trajectories.csv");
+        record(&path, TrajectoryFormat::Csv, 1, 1.0);
+        let frames = read_csv(&path);
+        let ids =
+            |frame: &Frame| -> Vec<u64> { frame.2.iter().map(|particle| particle.0).collect() };
+
+        let first = ids(&frames[0]);
+        assert_eq!(first.len(), 40);
+        assert!(frames[..3].iter().all(|frame| ids(frame) == first));
+        // After a particle is removed, the others keep their id.
+        assert!(frames[3..].iter().all(|frame| ids(frame) == first[..39]));
+    }
+
+    #[test]
+    fn sampling_is_deterministic() {
+        let directory = tempfile::tempdir().unwrap();
+        let first = directory.path().join("first.csv");
+        let second = directory.path().join("second.csv");
+        record(&first, TrajectoryFormat::Csv, 1, 0.5);
+        record(&second, TrajectoryFormat::Csv, 1, 0.5);
+        let frames = read_csv(&first);
+        assert_eq!(frames, read_csv(&second));
+
+        // The same particles in every frame, about half of them.
+        let sampled: Vec<u64> = frames[0].2.iter().map(|particle| particle.0).collect();
+        assert!((10..=30).contains(&sampled.len()), "{}", sampled.len());
+        for frame in &frames {
+            let ids: Vec<u64> = frame.2.iter().map(|particle| particle.0).collect();
+            assert!(ids.iter().all(|id| sampled.contains(id)));
+        }
+        assert!((0..40).all(|id| is_sampled(id, 0.5) == sampled.contains(&id)));
+        assert!((0..1000).all(|id| is_sampled(id, 1.0)));
+    }
+}
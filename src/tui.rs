@@ -0,0 +1,253 @@
+use std::{
+    fmt::Write as _,
+    io::{self, Write},
+    time::Duration,
+};
+
+use clap::ValueEnum;
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    execute, queue, style, terminal,
+};
+
+use physics_apple::{Error, Result, HEIGHT, WIDTH};
+
+/// How long to wait for a key press between two frames. Roughly matches a 60Hz display, which is
+/// what the window is limited to as well.
+const FRAME_WAIT: Duration = Duration::from_millis(16);
+
+/// Pixels darker than this are left out when drawing with braille dots.
+const BRAILLE_THRESHOLD: u32 = 0x40;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum TuiMode {
+    /// Two pixels per character ('▀'), each with its own colour.
+    #[default]
+    HalfBlock,
+    /// Eight pixels per character (braille dots). Sharper, but only one colour per character.
+    Braille,
+}
+
+impl TuiMode {
+    /// How many pixels a single character holds, horizontally and vertically.
+    fn pixels_per_char(&self) -> (u32, u32) {
+        match self {
+            TuiMode::HalfBlock => (1, 2),
+            TuiMode::Braille => (2, 4),
+        }
+    }
+}
+
+/// Puts the terminal in raw mode on an alternate screen, and restores it when dropped. This way
+/// the terminal is usable again even if the simulation panics.
+struct TerminalGuard;
+
+impl TerminalGuard {
+    fn enter() -> io::Result<TerminalGuard> {
+        terminal::enable_raw_mode()?;
+        execute!(io::stdout(), terminal::EnterAlternateScreen, cursor::Hide)?;
+        Ok(TerminalGuard)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = execute!(
+            io::stdout(),
+            style::ResetColor,
+            cursor::Show,
+            terminal::LeaveAlternateScreen
+        );
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Terminal counterpart of `gui::run`, for machines without a display. The frame drawn by
+/// `draw_function` is downsampled to fit the terminal. Exit with ESC, Q or Ctrl+C. Fails if the
+/// terminal cannot be set up or written to, or as soon as `update_function` fails.
+pub fn run<F1, F2>(mode: TuiMode, mut draw_function: F1, mut update_function: F2) -> Result<()>
+where
+    F1: FnMut(&mut [u8]),
+    F2: FnMut() -> Result<()>,
+{
+    let terminal_error = |err| Error::backend("The terminal", err);
+    let _guard = TerminalGuard::enter().map_err(terminal_error)?;
+    let mut stdout = io::BufWriter::new(io::stdout());
+    let mut frame = vec![0u8; (WIDTH * HEIGHT * 4) as usize];
+    let mut last_size = (0, 0);
+
+    loop {
+        // Wait for the first event at most a frame, then only take what is already pending. Key
+        // repeats come faster than frames, so a held key must not keep the loop waiting.
+        let mut wait = FRAME_WAIT;
+        while event::poll(wait).map_err(terminal_error)? {
+            if let Event::Key(key) = event::read().map_err(terminal_error)? {
+                if is_quit_key(key) {
+                    return Ok(());
+                }
+            }
+            wait = Duration::ZERO;
+        }
+
+        update_function()?;
+        draw_function(&mut frame);
+
+        let size = terminal::size().map_err(terminal_error)?;
+        if size != last_size {
+            queue!(stdout, terminal::Clear(terminal::ClearType::All)).map_err(terminal_error)?;
+            last_size = size;
+        }
+
+        let (columns, rows) = size;
+        let text = match mode {
+            TuiMode::HalfBlock => draw_half_blocks(&frame, columns as u32, rows as u32),
+            TuiMode::Braille => draw_braille(&frame, columns as u32, rows as u32),
+        };
+        queue!(stdout, cursor::MoveTo(0, 0), style::Print(text)).map_err(terminal_error)?;
+        stdout.flush().map_err(terminal_error)?;
+    }
+}
+
+fn is_quit_key(key: KeyEvent) -> bool {
+    key.kind == KeyEventKind::Press
+        && match key.code {
+            KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('Q') => true,
+            KeyCode::Char('c') => key.modifiers.contains(KeyModifiers::CONTROL),
+            _ => false,
+        }
+}
+
+/// An RGB image scaled down to fit the terminal, and where it should be placed.
+struct Downsampled {
+    width: u32,
+    height: u32,
+    /// Offset in characters, so that the image is centered.
+    column_offset: u32,
+    row_offset: u32,
+    pixels: Vec<[u8; 3]>,
+}
+
+impl Downsampled {
+    fn get(&self, x: u32, y: u32) -> [u8; 3] {
+        if x < self.width && y < self.height {
+            self.pixels[(x + y * self.width) as usize]
+        } else {
+            [0, 0, 0]
+        }
+    }
+}
+
+/// Box-filters the RGBA `frame` down to the largest size that fits in the terminal while keeping
+/// its aspect ratio.
+fn downsample(frame: &[u8], columns: u32, rows: u32, mode: TuiMode) -> Downsampled {
+    let (char_width, char_height) = mode.pixels_per_char();
+    let max_width = columns * char_width;
+    let max_height = rows * char_height;
+
+    let scale = f32::min(
+        max_width as f32 / WIDTH as f32,
+        max_height as f32 / HEIGHT as f32,
+    );
+    let width = ((WIDTH as f32 * scale) as u32).clamp(1, max_width.max(1));
+    let height = ((HEIGHT as f32 * scale) as u32).clamp(1, max_height.max(1));
+
+    let mut pixels = Vec::with_capacity((width * height) as usize);
+    for y in 0..height {
+        let y0 = y * HEIGHT / height;
+        let y1 = ((y + 1) * HEIGHT / height).max(y0 + 1);
+        for x in 0..width {
+            let x0 = x * WIDTH / width;
+            let x1 = ((x + 1) * WIDTH / width).max(x0 + 1);
+
+            let mut sum = [0u32; 3];
+            for source_y in y0..y1 {
+                for source_x in x0..x1 {
+                    let index = ((source_x + source_y * WIDTH) * 4) as usize;
+                    for channel in 0..3 {
+                        sum[channel] += frame[index + channel] as u32;
+                    }
+                }
+            }
+            let count = (x1 - x0) * (y1 - y0);
+            pixels.push(sum.map(|value| (value / count) as u8));
+        }
+    }
+
+    Downsampled {
+        width,
+        height,
+        column_offset: (columns - width.div_ceil(char_width).min(columns)) / 2,
+        row_offset: (rows - height.div_ceil(char_height).min(rows)) / 2,
+        pixels,
+    }
+}
+
+fn draw_half_blocks(frame: &[u8], columns: u32, rows: u32) -> String {
+    let image = downsample(frame, columns, rows, TuiMode::HalfBlock);
+    let mut text = String::new();
+
+    for row in 0..image.height.div_ceil(2) {
+        let _ = write!(
+            text,
+            "{}",
+            cursor::MoveTo(image.column_offset as u16, (image.row_offset + row) as u16)
+        );
+        for x in 0..image.width {
+            let [top_r, top_g, top_b] = image.get(x, row * 2);
+            let [bottom_r, bottom_g, bottom_b] = image.get(x, row * 2 + 1);
+            let _ = write!(
+                text,
+                "\x1b[38;2;{top_r};{top_g};{top_b}m\x1b[48;2;{bottom_r};{bottom_g};{bottom_b}m▀"
+            );
+        }
+        text.push_str("\x1b[0m");
+    }
+
+    text
+}
+
+fn draw_braille(frame: &[u8], columns: u32, rows: u32) -> String {
+    // Bit of each dot inside a braille character, indexed by [y][x].
+    const DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+
+    let image = downsample(frame, columns, rows, TuiMode::Braille);
+    let mut text = String::new();
+
+    for row in 0..image.height.div_ceil(4) {
+        let _ = write!(
+            text,
+            "{}",
+            cursor::MoveTo(image.column_offset as u16, (image.row_offset + row) as u16)
+        );
+        for column in 0..image.width.div_ceil(2) {
+            let mut dots = 0;
+            let mut lit = 0;
+            let mut sum = [0u32; 3];
+            for (dot_y, dot_row) in DOTS.iter().enumerate() {
+                for (dot_x, bit) in dot_row.iter().enumerate() {
+                    let color = image.get(column * 2 + dot_x as u32, row * 4 + dot_y as u32);
+                    let brightness = color.iter().map(|&c| c as u32).max().unwrap();
+                    if brightness >= BRAILLE_THRESHOLD {
+                        dots |= bit;
+                        lit += 1;
+                        for channel in 0..3 {
+                            sum[channel] += color[channel] as u32;
+                        }
+                    }
+                }
+            }
+
+            if lit == 0 {
+                text.push(' ');
+            } else {
+                let [r, g, b] = sum.map(|value| value / lit);
+                let glyph = char::from_u32(0x2800 + dots).unwrap();
+                let _ = write!(text, "\x1b[38;2;{r};{g};{b}m{glyph}");
+            }
+        }
+        text.push_str("\x1b[0m");
+    }
+
+    text
+}
@@ -0,0 +1,195 @@
+//! Golden-image regression tests: a short seeded sequence of tiny synthetic frames goes through
+//! field generation, simulation and rendering, and every rendered frame is compared with the
+//! PNG checked in under `tests/golden/<case>/`.
+//!
+//! When a frame differs, the rendered frame and an image of the differences are written to
+//! `target/tmp/golden/<case>/`. After an intended change of the output, run the tests with
+//! `UPDATE_GOLDEN=1` to replace the golden images, and check the new ones before committing them.
+
+use std::path::{Path, PathBuf};
+
+use image::{GrayImage, Luma, Rgba, RgbaImage};
+use physics_apple::{
+    config::{FrameRate, PhysicsConfig, RenderStyle, SequenceConfig},
+    render,
+    schedule::Playback,
+    seeded_board, Config, Frame,
+};
+
+/// Size of the synthetic frames.
+const SIZE: u32 = 32;
+/// How much a channel may differ before a pixel counts as different.
+const CHANNEL_TOLERANCE: u8 = 8;
+/// Fraction of the pixels of a frame that may differ, for rounding that varies between platforms.
+const PIXEL_TOLERANCE: f64 = 0.005;
+
+/// Draws the input frames into `directory`: a disc moving to the right, which turns into a
+/// square on the last frame.
+fn synthetic_frames(directory: &Path) -> Vec<Frame> {
+    (0..4)
+        .map(|index| {
+            let center = 8.0 + 5.0 * index as f32;
+            let image = GrayImage::from_fn(SIZE, SIZE, |x, y| {
+                let (dx, dy) = (x as f32 - center, y as f32 - 16.0);
+                let inside = if index < 3 {
+                    dx.hypot(dy) <= 6.0
+                } else {
+                    dx.abs() <= 5.0 && dy.abs() <= 5.0
+                };
+                Luma([if inside { 255 } else { 0 }])
+            });
+            let path = directory.join(format!("frame.{}.png", index));
+            image.save(&path).unwrap();
+            Frame::File(path)
+        })
+        .collect()
+}
+
+/// The configuration of every case: 4 input frames of 150 updates each, one output frame per
+/// input frame and two more after the last one.
+fn config() -> Config {
+    Config {
+        seed: Some(7),
+        sequence: SequenceConfig {
+            input_fps: FrameRate::from(4),
+            output_fps: FrameRate::from(4),
+            end_frames: 2,
+            pixels_per_particle: 4,
+            ..SequenceConfig::default()
+        },
+        physics: PhysicsConfig {
+            // Particles settle on the shapes instead of swinging around them, so that they show.
+            drag: 0.05,
+            ..PhysicsConfig::default()
+        },
+        ..Config::default()
+    }
+}
+
+/// Simulates the synthetic sequence with the driver of `simulate-sequence --save-to-file`, and
+/// returns the rendered frames.
+fn render_sequence(config: &Config) -> Vec<RgbaImage> {
+    let input = tempfile::tempdir().unwrap();
+    let output = tempfile::tempdir().unwrap();
+    let frames = synthetic_frames(input.path());
+
+    let mut board = seeded_board(&frames[0], config, config.sequence.pixels_per_particle).unwrap();
+    let mut buffer = render::render_buffer(&board, config);
+    let mut playback = Playback::new(frames, config);
+    render::render_sequence(
+        &mut playback,
+        &mut board,
+        &mut buffer,
+        config,
+        output.path(),
+        0,
+        |_, _| (),
+        |_, _, _, _| (),
+    )
+    .unwrap();
+
+    (1..=playback.output_frames())
+        .map(|frame| {
+            let path = output.path().join(format!("render.{}.png", frame));
+            image::open(&path).unwrap().into_rgba8()
+        })
+        .collect()
+}
+
+/// Compares `rendered` with the golden images of `case`, or replaces them with `UPDATE_GOLDEN`.
+fn check_golden(case: &str, rendered: &[RgbaImage]) {
+    let golden_dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(case);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all(&golden_dir).unwrap();
+        for (index, frame) in rendered.iter().enumerate() {
+            frame
+                .save(golden_dir.join(format!("render.{}.png", index + 1)))
+                .unwrap();
+        }
+        return;
+    }
+
+    let diff_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR"))
+        .join("golden")
+        .join(case);
+    let _ = std::fs::remove_dir_all(&diff_dir);
+    let mut failures = vec![];
+    for (index, frame) in rendered.iter().enumerate() {
+        let name = format!("render.{}", index + 1);
+        let golden_path = golden_dir.join(format!("{}.png", name));
+        let golden = match image::open(&golden_path) {
+            Ok(golden) => golden.into_rgba8(),
+            Err(err) => {
+                failures.push(format!("{}: {}", golden_path.display(), err));
+                continue;
+            }
+        };
+        if golden.dimensions() != frame.dimensions() {
+            failures.push(format!(
+                "{}: {:?} instead of {:?}",
+                name,
+                frame.dimensions(),
+                golden.dimensions()
+            ));
+            continue;
+        }
+
+        let (diff, different) = diff_image(frame, &golden);
+        let fraction = different as f64 / (frame.width() * frame.height()) as f64;
+        if fraction > PIXEL_TOLERANCE {
+            std::fs::create_dir_all(&diff_dir).unwrap();
+            frame
+                .save(diff_dir.join(format!("{}.actual.png", name)))
+                .unwrap();
+            diff.save(diff_dir.join(format!("{}.diff.png", name)))
+                .unwrap();
+            failures.push(format!("{}: {} pixel(s) differ", name, different));
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{} frame(s) of '{}' differ from the golden images (see '{}', or run with \
+         UPDATE_GOLDEN=1 if the change is intended):\n  {}",
+        failures.len(),
+        case,
+        diff_dir.display(),
+        failures.join("\n  ")
+    );
+}
+
+/// Shows the golden image dimmed, with the pixels that differ in red, and counts them.
+fn diff_image(actual: &RgbaImage, golden: &RgbaImage) -> (RgbaImage, usize) {
+    let mut different = 0;
+    let diff = RgbaImage::from_fn(actual.width(), actual.height(), |x, y| {
+        let (a, g) = (actual.get_pixel(x, y), golden.get_pixel(x, y));
+        if a.0
+            .iter()
+            .zip(g.0)
+            .any(|(a, g)| a.abs_diff(g) > CHANNEL_TOLERANCE)
+        {
+            different += 1;
+            Rgba([255, 0, 0, 255])
+        } else {
+            let value = g.0[0] / 3;
+            Rgba([value, value, value, 255])
+        }
+    });
+    (diff, different)
+}
+
+#[test]
+fn solid_sequence() {
+    let config = config();
+    check_golden("solid", &render_sequence(&config));
+}
+
+#[test]
+fn upscaled_density_sequence() {
+    let mut config = config();
+    config.render.render_scale = 2;
+    config.render.style = RenderStyle::Density;
+    check_golden("upscaled-density", &render_sequence(&config));
+}